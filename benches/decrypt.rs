@@ -0,0 +1,37 @@
+
+//! Throughput of decrypting a track with different read buffer sizes.
+//! Run using `cargo bench`.
+
+use std::{io::Read, time::Instant};
+
+use rizzle::{TrackDecryptor, TrackEncryptor, TrackId};
+
+const TRACK_SIZE: usize = 16 * 1024 * 1024;
+
+fn main() {
+
+    let plain: Vec<u8> = (0..TRACK_SIZE).map(|idx| (idx * 31 % 251) as u8).collect();
+    let mut encrypted = Vec::with_capacity(TRACK_SIZE);
+    TrackEncryptor::for_track(&plain[..], TrackId(3135556)).read_to_end(&mut encrypted).unwrap();
+
+    for size in [1, 64, 1000, 2048, 4096, 64 * 1024, 1024 * 1024] {
+
+        let mut buff = vec![0; size];
+        let mut total = 0;
+
+        let start = Instant::now();
+        let mut decryptor = TrackDecryptor::for_track(&encrypted[..], TrackId(3135556));
+        loop {
+            let len = decryptor.read(&mut buff).unwrap();
+            if len == 0 { break }
+            total += len;
+        }
+        let elapsed = start.elapsed();
+
+        assert_eq!(total, TRACK_SIZE);
+        let rate = TRACK_SIZE as f64 / 1024.0 / 1024.0 / elapsed.as_secs_f64();
+        println!("buffer size {:>8}: {:>8.1} MiB/s ({:?})", size, rate, elapsed);
+
+    }
+
+}
@@ -1,26 +1,25 @@
 
-use std::{io::{self, Read}, iter::{zip, repeat}};
+use std::{io::{self, Read, Seek, SeekFrom}, iter::{zip, repeat}, pin::Pin, task::{Context, Poll}, future::Future};
 
 use cipher::{KeyInit, BlockEncrypt, BlockDecrypt};
-use futures_lite::io::BlockOn;
+use futures_lite::{io::BlockOn, AsyncRead, AsyncSeek, AsyncSeekExt, Stream, ready, future};
 use generic_array::GenericArray;
-use tinyvec::{SliceVec, ArrayVec};
 
-use crate::Track;
+use crate::{Track, TrackId, Format, Listen, ListenContext, ListenReporter, AutoReport, RateLimiter, Error, throttle::Throttle, url::header};
 
-pub(crate) fn generate_blowfish_key(track_details: &Track) -> String {
+/// Derive the blowfish key a track is encrypted with from its id.
+pub fn track_key(track: TrackId) -> [u8; 16] {
 
     let key = b"g4el58wc0zvf9na1";
 
-    let id_md5 = md5::compute(track_details.id.to_string().as_bytes());
+    let id_md5 = md5::compute(track.to_string().as_bytes());
     let id_md5_str = hex::encode(id_md5.0);
     let id_md5_bytes = id_md5_str.as_bytes();
 
-    let mut result = String::with_capacity(16);
+    let mut result = [0; 16];
 
     for idx in 0..16 {
-        let value = id_md5_bytes[idx] ^ id_md5_bytes[idx + 16] ^ key[idx];
-        result.push(value as char);
+        result[idx] = id_md5_bytes[idx] ^ id_md5_bytes[idx + 16] ^ key[idx];
     }
 
     result
@@ -67,94 +66,799 @@ pub(crate) fn generate_url_key(track_details: &Track, quality: usize) -> String
 
 }
 
-pub struct Mp3Stream {
-    reader: BlockOn<rtv::BodyReader>, // we can just use BlockOn since rtv provides it's own reactor
+/// Request a track from the cdn, starting at `offset` which has to be a multiple of 2048.
+pub(crate) async fn open_track(client: &mut rtv::SimpleClient, track: &Track, format: Format, offset: u64) -> Result<AsyncMp3Stream, Error> {
+
+    debug_assert!(offset % 2048 == 0, "offset not on a chunk boundary");
+
+    let url = CdnUrl::new(track, format)?;
+    let range = format!("bytes={}-", offset);
+    let mut req = rtv::Request::get().secure()
+        .host(&url.host)
+        .path(&url.path);
+
+    if offset > 0 {
+        req = req.set("Range", &range);
+    }
+
+    let resp = client.stream(req).await?;
+    check_range(&resp.head, offset)?;
+
+    Ok(AsyncMp3Stream::new(resp, url, track, format, offset))
+
+}
+
+/// Make sure the cdn answered with the range we asked for, otherwise the wrong chunks
+/// would be decrypted. Without a range a complete response is fine too.
+pub(crate) fn check_range(head: &rtv::ResponseHead, offset: u64) -> Result<(), Error> {
+    check_range_start(head.status.code, &head.status.text, header(head, "Content-Range"), offset)
+}
+
+/// See `check_range`, `content_range` is the value of the `Content-Range` header.
+pub(crate) fn check_range_start(code: u16, text: &str, content_range: Option<&str>, offset: u64) -> Result<(), Error> {
+
+    let start = match code {
+        200 if offset == 0 => return Ok(()),
+        206 => content_range
+            .and_then(|val| val.strip_prefix("bytes "))
+            .and_then(|val| val.split('-').next())
+            .and_then(|val| val.trim().parse::<u64>().ok()),
+        code => return Err(Error::HttpError(format!("{} {} for a range starting at {}", code, text, offset))),
+    };
+
+    match start {
+        Some(val) if val == offset => Ok(()),
+        Some(val) => Err(Error::HttpError(format!("asked for a range starting at {}, got one starting at {}", offset, val))),
+        None => Err(Error::HttpError(format!("missing or invalid content range for a range starting at {}", offset))),
+    }
+
+}
+
+/// The audio of a podcast episode, it isn't encrypted.
+pub struct EpisodeStream {
+    reader: BlockOn<rtv::BodyReader>,
+}
+
+impl EpisodeStream {
+
+    pub(crate) fn new(reader: rtv::BodyReader) -> Self {
+        Self { reader: BlockOn::new(reader) }
+    }
+
+}
+
+impl Read for EpisodeStream {
+
+    fn read(&mut self, buff: &mut [u8]) -> io::Result<usize> {
+        self.reader.read(buff)
+    }
+
+}
+
+/// Size of the chunks deezer splits a track into.
+pub const CHUNK_SIZE: usize = 2048;
+
+/// The key of a track, decrypts or encrypts single chunks without needing a reader.
+/// Useful if the chunks don't arrive in order, for example from a cache.
+#[derive(Clone)]
+pub struct TrackCipher {
     blowfish: blowfish::Blowfish,
-    count: usize,
-    storage: ArrayVec<[u8; 2048]>,
 }
 
-impl Mp3Stream {
+impl TrackCipher {
 
-    pub(crate) fn new(reader: rtv::BodyReader, key: &[u8]) -> Self {
+    pub fn new(key: &[u8; 16]) -> Self {
+        Self { blowfish: blowfish::Blowfish::new_from_slice(key).expect("Invalid blowfish key") }
+    }
+
+    /// Use the key of the track with this id.
+    pub fn for_track(track: TrackId) -> Self {
+        Self::new(&track_key(track))
+    }
+
+    /// Decrypt a single chunk in place, `index` is the index of the chunk in the track.
+    /// Chunks that aren't encrypted or aren't 2048 bytes long are left alone.
+    pub fn decrypt_chunk(&self, chunk: &mut [u8], index: u64) {
+        if chunk.len() == CHUNK_SIZE && index % 3 == 0 {
+            decrypt_chunk(&self.blowfish, chunk);
+        }
+    }
+
+    /// Encrypt a single chunk in place, see `decrypt_chunk`.
+    pub fn encrypt_chunk(&self, chunk: &mut [u8], index: u64) {
+        if chunk.len() == CHUNK_SIZE && index % 3 == 0 {
+            encrypt_chunk(&self.blowfish, chunk);
+        }
+    }
+
+}
+
+/// Decrypts a track as it's served by deezers cdn.
+///
+/// Deezer encrypts every third 2048 byte chunk using blowfish in cbc mode,
+/// the last chunk is never encrypted since it isn't a full one.
+/// Wraps any `Read` or `AsyncRead`, so already downloaded files can be decrypted as well.
+pub struct TrackDecryptor<R> {
+    reader: R,
+    cipher: TrackCipher,
+    /// Index of the current chunk, every third one is encrypted.
+    index: u64,
+    chunk: Vec<u8>,
+    /// How much of the chunk was received.
+    filled: usize,
+    /// How much of the chunk was already handed out.
+    pos: usize,
+    /// Bytes to throw away at the start of the next chunk.
+    skip: usize,
+    /// The position in the decrypted track.
+    offset: u64,
+    eof: bool,
+}
+
+impl<R> TrackDecryptor<R> {
+
+    pub fn new(reader: R, key: &[u8; 16]) -> Self {
         Self {
-            reader: BlockOn::new(reader),
-            blowfish: blowfish::Blowfish::new_from_slice(key).expect("Invalid blowfish key"),
-            count: 0,
-            storage: Default::default(),
+            reader,
+            cipher: TrackCipher::new(key),
+            index: 0,
+            chunk: vec![0; CHUNK_SIZE],
+            filled: 0,
+            pos: 0,
+            skip: 0,
+            offset: 0,
+            eof: false,
         }
     }
 
+    /// Use the key of the track with this id.
+    pub fn for_track(reader: R, track: TrackId) -> Self {
+        Self::new(reader, &track_key(track))
+    }
+
+    /// Start at `position` in the track instead of at the beginning.
+    /// The reader has to start at the chunk that contains `position`,
+    /// so at `position - position % 2048`.
+    pub fn starting_at(mut self, position: u64) -> Self {
+        self.reset(position);
+        self
+    }
+
+    /// The position in the decrypted track.
+    pub fn position(&self) -> u64 {
+        self.offset
+    }
+
+    pub fn get_ref(&self) -> &R {
+        &self.reader
+    }
+
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.reader
+    }
+
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+
+    /// Continue reading from a different reader, see `starting_at`.
+    pub(crate) fn replace_reader(&mut self, reader: R, position: u64) {
+        self.reader = reader;
+        self.reset(position);
+    }
+
+    fn reset(&mut self, position: u64) {
+        self.index = position / CHUNK_SIZE as u64;
+        self.skip = (position % CHUNK_SIZE as u64) as usize;
+        self.offset = position;
+        self.filled = 0;
+        self.pos = 0;
+        self.eof = false;
+    }
+
+    /// Move to another position inside of the current chunk, without reading anything.
+    /// Returns false if the position isn't buffered.
+    pub(crate) fn seek_buffered(&mut self, target: u64) -> bool {
+        let chunk_start = self.offset - self.pos as u64;
+        if self.skip == 0 && target >= chunk_start && target < chunk_start + self.filled as u64 {
+            self.pos = (target - chunk_start) as usize;
+            self.offset = target;
+            return true
+        }
+        false
+    }
+
+    /// Everything handed out, start the next chunk.
+    fn next_chunk(&mut self) {
+        if self.pos == self.filled && self.filled == CHUNK_SIZE {
+            self.pos = 0;
+            self.filled = 0;
+        }
+    }
+
+    fn needs_data(&self) -> bool {
+        self.filled < CHUNK_SIZE && !self.eof
+    }
+
+    fn on_read(&mut self, bytes_read: usize) {
+        if bytes_read == 0 {
+            self.eof = true;
+            return
+        }
+        self.filled += bytes_read;
+        if self.filled == CHUNK_SIZE {
+            self.cipher.decrypt_chunk(&mut self.chunk, self.index);
+            self.index += 1;
+        }
+    }
+
+    /// If nothing is buffered and the callers buffer fits at least one chunk, we can read
+    /// straight into it. Returns how much of it to use, always a multiple of the chunk size.
+    fn direct_len(&self, buff: &[u8]) -> Option<usize> {
+        if self.filled == 0 && self.skip == 0 && !self.eof && buff.len() >= CHUNK_SIZE {
+            Some(buff.len() - buff.len() % CHUNK_SIZE)
+        } else {
+            None
+        }
+    }
+
+    /// Decrypts the full chunks that were read straight into the callers buffer, an incomplete one
+    /// at the end is moved into the internal buffer. Returns the amount of bytes ready in `buff`,
+    /// or `None` if there wasn't a full chunk and the read has to go through the internal buffer.
+    fn on_read_direct(&mut self, buff: &mut [u8], bytes_read: usize) -> Option<usize> {
+        if bytes_read == 0 {
+            self.eof = true;
+            return Some(0)
+        }
+        let full = bytes_read - bytes_read % CHUNK_SIZE;
+        for chunk in buff[..full].chunks_exact_mut(CHUNK_SIZE) {
+            self.cipher.decrypt_chunk(chunk, self.index);
+            self.index += 1;
+        }
+        let rest = bytes_read - full;
+        self.chunk[..rest].copy_from_slice(&buff[full..bytes_read]);
+        self.filled = rest;
+        self.offset += full as u64;
+        if full > 0 { Some(full) } else { None }
+    }
+
+    /// The decrypted data of the current chunk, empty at the end of the track.
+    fn ready_data(&mut self) -> &[u8] {
+        if self.skip > 0 {
+            self.pos = self.skip.min(self.filled);
+            self.skip = 0;
+        }
+        &self.chunk[self.pos..self.filled]
+    }
+
+    pub(crate) fn consume(&mut self, amount: usize) {
+        self.pos += amount;
+        self.offset += amount as u64;
+    }
+
 }
 
-impl<'a> Read for Mp3Stream {
+impl<R: Read> TrackDecryptor<R> {
+
+    /// Make sure there is decrypted data available in the current chunk.
+    /// Returns the data or an empty slice at the end of the track.
+    pub(crate) fn fill(&mut self) -> io::Result<&[u8]> {
+        self.next_chunk();
+        while self.needs_data() {
+            match self.reader.read(&mut self.chunk[self.filled..]) {
+                Ok(bytes_read) => self.on_read(bytes_read),
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => {},
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(self.ready_data())
+    }
 
-    fn read(&mut self, buff: &mut [u8]) -> std::io::Result<usize> {
+}
 
-        // good luck understanding this
+impl<R: AsyncRead + Unpin> TrackDecryptor<R> {
+
+    /// Async version of `fill`.
+    pub(crate) fn poll_fill(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<&[u8]>> {
+        self.next_chunk();
+        while self.needs_data() {
+            match Pin::new(&mut self.reader).poll_read(cx, &mut self.chunk[self.filled..]) {
+                Poll::Ready(Ok(bytes_read)) => self.on_read(bytes_read),
+                Poll::Ready(Err(err)) if err.kind() == io::ErrorKind::Interrupted => {},
+                Poll::Ready(Err(err)) => return Poll::Ready(Err(err)),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+        Poll::Ready(Ok(self.ready_data()))
+    }
 
-        let mut dest = SliceVec::from(buff);
-        dest.clear();
+}
+
+impl<R: Read> Read for TrackDecryptor<R> {
 
-        let len = dest.capacity();
-        let bytes_read;
+    fn read(&mut self, buff: &mut [u8]) -> io::Result<usize> {
 
-        // if we have more bytes stored then requested just return them
-        // and shrink the storage
-        if len <= self.storage.len() {
-            dest.extend(self.storage.drain(..len));
-            return Ok(len);
+        if buff.is_empty() {
+            return Ok(0)
         }
 
-        // calculate how many bytes we need to read after using 
-        // the stored ones
-        let new_len = len - self.storage.len();
+        self.next_chunk();
+        if let Some(direct) = self.direct_len(buff) {
+            let bytes_read = loop {
+                match self.reader.read(&mut buff[..direct]) {
+                    Ok(val) => break val,
+                    Err(err) if err.kind() == io::ErrorKind::Interrupted => {},
+                    Err(err) => return Err(err),
+                }
+            };
+            if let Some(len) = self.on_read_direct(buff, bytes_read) {
+                return Ok(len)
+            }
+        }
 
-        // calculate how many bytes we need to read in order to always
-        // read on a 2048 byte block boundry
-        let to_read = new_len + (2048 - new_len % 2048);
+        let data = self.fill()?;
+        let len = data.len().min(buff.len());
+        buff[..len].copy_from_slice(&data[..len]);
+        self.consume(len);
 
-        dest.extend(self.storage.drain(..));
+        Ok(len)
 
-        // read the data, if there is less data on the reader left then
-        // requested (the last chunk), this block is not encrypted
-        let mut data = vec![0; to_read];
-        match try_read_exact(&mut self.reader, &mut data) {
-            ReadExact::Ok => bytes_read = len,
-            ReadExact::Eof(val) => bytes_read = val,
-            ReadExact::Err(err) => return Err(err),
-        };
+    }
+
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for TrackDecryptor<R> {
 
-        // decrypt all blocks that need to be decrypted
-        for chunk in data.chunks_mut(2048) {
-            if chunk.len() == 2048 && self.count % 3 == 0 {
-                // note: this is a manual implementation of blowfish cbc mode
-                // (took way too long to figure out)
-                let mut cbc_xor = *b"\x00\x01\x02\x03\x04\x05\x06\x07"; // magic iv
-                let mut block_copy = [0; 8];
-                for block in chunk.chunks_exact_mut(8) {
-                    block_copy.copy_from_slice(block);
-                    self.blowfish.decrypt_block(GenericArray::from_mut_slice(block));
-                    zip(block.iter_mut(), cbc_xor).for_each(|(byte, val)| *byte ^= val);
-                    cbc_xor = block_copy;
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buff: &mut [u8]) -> Poll<io::Result<usize>> {
+
+        let this = self.get_mut();
+
+        if buff.is_empty() {
+            return Poll::Ready(Ok(0))
+        }
+
+        this.next_chunk();
+        if let Some(direct) = this.direct_len(buff) {
+            let bytes_read = loop {
+                match ready!(Pin::new(&mut this.reader).poll_read(cx, &mut buff[..direct])) {
+                    Ok(val) => break val,
+                    Err(err) if err.kind() == io::ErrorKind::Interrupted => {},
+                    Err(err) => return Poll::Ready(Err(err)),
                 }
+            };
+            if let Some(len) = this.on_read_direct(buff, bytes_read) {
+                return Poll::Ready(Ok(len))
             }
-            self.count += 1;
         }
 
-        if bytes_read >= new_len {
-            dest.extend(data.drain(..new_len));
-            self.storage.extend(data);
-        } else {
-            dest.extend(data.drain(..bytes_read));
+        let data = ready!(this.poll_fill(cx))?;
+        let len = data.len().min(buff.len());
+        buff[..len].copy_from_slice(&data[..len]);
+        this.consume(len);
+
+        Poll::Ready(Ok(len))
+
+    }
+
+}
+
+/// The inverse of `TrackDecryptor`, encrypts a plain track the same way deezers cdn does.
+/// Useful for test fixtures or serving tracks locally.
+pub struct TrackEncryptor<R> {
+    reader: R,
+    cipher: TrackCipher,
+    index: u64,
+    chunk: Vec<u8>,
+    filled: usize,
+    pos: usize,
+    eof: bool,
+}
+
+impl<R> TrackEncryptor<R> {
+
+    pub fn new(reader: R, key: &[u8; 16]) -> Self {
+        Self {
+            reader,
+            cipher: TrackCipher::new(key),
+            index: 0,
+            chunk: vec![0; CHUNK_SIZE],
+            filled: 0,
+            pos: 0,
+            eof: false,
         }
+    }
+
+    /// Use the key of the track with this id.
+    pub fn for_track(reader: R, track: TrackId) -> Self {
+        Self::new(reader, &track_key(track))
+    }
+
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+
+}
+
+impl<R: Read> Read for TrackEncryptor<R> {
+
+    fn read(&mut self, buff: &mut [u8]) -> io::Result<usize> {
+
+        if buff.is_empty() {
+            return Ok(0)
+        }
+
+        if self.pos == self.filled && self.filled == CHUNK_SIZE {
+            self.pos = 0;
+            self.filled = 0;
+        }
+
+        // a chunk can only be encrypted once it's complete
+        while self.filled < CHUNK_SIZE && !self.eof {
+            match self.reader.read(&mut self.chunk[self.filled..]) {
+                Ok(0) => self.eof = true,
+                Ok(bytes_read) => self.filled += bytes_read,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => {},
+                Err(err) => return Err(err),
+            }
+            if self.filled == CHUNK_SIZE {
+                self.cipher.encrypt_chunk(&mut self.chunk, self.index);
+                self.index += 1;
+            }
+        }
+
+        let data = &self.chunk[self.pos..self.filled];
+        let len = data.len().min(buff.len());
+        buff[..len].copy_from_slice(&data[..len]);
+        self.pos += len;
 
-        Ok(bytes_read)
+        Ok(len)
 
     }
 
 }
 
+type PendingSeek = Pin<Box<dyn Future<Output = (rtv::SimpleClient, io::Result<rtv::StreamResponse>)> + Send>>;
+
+/// Where the encrypted track can be downloaded from.
+#[derive(Clone)]
+pub(crate) struct CdnUrl {
+    pub(crate) host: String,
+    pub(crate) path: String,
+}
+
+impl CdnUrl {
+
+    /// Fails if deezer didn't tell us the md5 origin of the track, the host depends on it.
+    pub(crate) fn new(track: &Track, format: Format) -> Result<Self, Error> {
+        let shard = track.md5_origin.get(0..1).ok_or_else(|| Error::NotFound(format!("md5 origin of track {}", track.id)))?;
+        Ok(Self {
+            host: format!("e-cdns-proxy-{}.dzcdn.net", shard),
+            path: format!("/mobile/1/{}", generate_url_key(track, format.quality())),
+        })
+    }
+
+}
+
+/// The decrypted mp3 data of a track, read asynchronously.
+/// Implements `AsyncRead` and `Stream`, which yields the data in chunks of up to 2048 bytes.
+///
+/// Seeking sends a new request starting at the 2048 byte chunk that contains
+/// the new position, so it doesn't have to download everything before it.
+pub struct AsyncMp3Stream {
+    inner: TrackDecryptor<rtv::BodyReader>,
+    /// The length of the whole track, zero if unknown.
+    len: u64,
+    url: CdnUrl,
+    /// Only created when seeking.
+    client: Option<rtv::SimpleClient>,
+    seeking: Option<(u64, PendingSeek)>,
+    track: TrackId,
+    /// In seconds, zero if unknown.
+    duration: u64,
+    format: Format,
+    report: Option<AutoReport>,
+    throttle: Throttle,
+    /// The `Stream` ended or failed, it only yields `None` until the next seek.
+    ended: bool,
+}
+
+impl AsyncMp3Stream {
+
+    /// The response has to start at `offset`, which must be on a chunk boundary.
+    /// The size deezer told us is preferred over the content length, since a broken response
+    /// might be shorter than the track.
+    pub(crate) fn new(resp: rtv::StreamResponse, url: CdnUrl, track: &Track, format: Format, offset: u64) -> Self {
+        let len = resp.head.content_length as u64;
+        Self {
+            inner: TrackDecryptor::for_track(resp.body, track.id).starting_at(offset),
+            len: track.size(format).unwrap_or(if len > 0 { len + offset } else { 0 }),
+            url,
+            client: None,
+            seeking: None,
+            track: track.id,
+            duration: track.duration,
+            format,
+            report: None,
+            throttle: Throttle::default(),
+            ended: false,
+        }
+    }
+
+    /// The length of the track in bytes, if deezer or the cdn told us.
+    pub fn size(&self) -> Option<u64> {
+        if self.len > 0 { Some(self.len) } else { None }
+    }
+
+    /// The current position in the track.
+    pub fn position(&self) -> u64 {
+        self.inner.position()
+    }
+
+    fn poll_seek_to(&mut self, cx: &mut Context<'_>, target: u64) -> Poll<io::Result<u64>> {
+
+        // seeking inside of the current chunk doesn't need a new request
+        if self.seeking.is_none() && self.inner.seek_buffered(target) {
+            self.ended = false;
+            return Poll::Ready(Ok(target))
+        }
+
+        // a different seek might still be running, replace it
+        if !matches!(&self.seeking, Some((pending, ..)) if *pending == target) {
+            let mut client = match self.client.take() {
+                Some(val) => val,
+                None => rtv::SimpleClient::new()?,
+            };
+            let host = self.url.host.clone();
+            let path = self.url.path.clone();
+            let aligned = target - target % CHUNK_SIZE as u64;
+            let range = format!("bytes={}-", aligned);
+            let fut = async move {
+                let req = rtv::Request::get().secure()
+                    .host(&host)
+                    .path(&path)
+                    .set("Range", &range);
+                let resp = client.stream(req).await.and_then(|resp| match check_range(&resp.head, aligned) {
+                    Ok(()) => Ok(resp),
+                    Err(err) => Err(io::Error::new(io::ErrorKind::InvalidData, err)),
+                });
+                (client, resp)
+            };
+            self.seeking = Some((target, Box::pin(fut)));
+        }
+
+        let (client, resp) = match &mut self.seeking {
+            Some((_, fut)) => ready!(fut.as_mut().poll(cx)),
+            None => unreachable!(),
+        };
+
+        self.seeking = None;
+        self.client = Some(client);
+
+        self.inner.replace_reader(resp?.body, target);
+        self.ended = false;
+
+        Poll::Ready(Ok(target))
+
+    }
+
+    /// Queue a listen in the reporter once the stream is finished or dropped,
+    /// if enough of the track was played. Unless the player calls `played`, the duration
+    /// is estimated from the bytes read, skipping over parts of the track doesn't count.
+    pub fn report_to(mut self, reporter: &ListenReporter, context: ListenContext) -> Self {
+        let listen = Listen::new(self.track, self.format, context);
+        self.report = Some(AutoReport::new(reporter.clone(), listen, self.duration));
+        self
+    }
+
+    /// Tell the stream how many seconds of the track were actually played, this is
+    /// reported instead of the estimate. Players usually read ahead, so this is more accurate.
+    pub fn played(&mut self, seconds: u64) {
+        if let Some(report) = &mut self.report {
+            report.played(seconds);
+        }
+    }
+
+    /// Don't read faster than the limiter allows. Several limiters can be used,
+    /// for example a global one and one just for this stream.
+    pub fn throttle(mut self, limiter: &RateLimiter) -> Self {
+        self.throttle.add(limiter.clone());
+        self
+    }
+
+    /// Make sure there is decrypted data available in the current chunk.
+    /// Returns the data or an empty slice at the end of the stream.
+    fn poll_chunk(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<&[u8]>> {
+
+        let position = self.inner.position();
+        let data = ready!(self.inner.poll_fill(cx))?;
+
+        if data.is_empty() {
+            check_complete(position, self.len)?;
+            if let Some(report) = &mut self.report {
+                report.finish();
+            }
+        }
+
+        Poll::Ready(Ok(data))
+
+    }
+
+    fn consume(&mut self, amount: usize) {
+        let position = self.inner.position();
+        self.inner.consume(amount);
+        if let Some(report) = &mut self.report {
+            report.advance(position, amount);
+        }
+    }
+
+}
+
+impl AsyncRead for AsyncMp3Stream {
+
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buff: &mut [u8]) -> Poll<io::Result<usize>> {
+
+        let this = self.get_mut();
+
+        if buff.is_empty() {
+            return Poll::Ready(Ok(0))
+        }
+
+        let position = this.inner.position();
+        let len = ready!(this.throttle.poll_read(cx, &mut this.inner, buff))?;
+
+        if len == 0 {
+            check_complete(this.inner.position(), this.len)?;
+        }
+
+        if let Some(report) = &mut this.report {
+            if len == 0 { report.finish() } else { report.advance(position, len) }
+        }
+
+        Poll::Ready(Ok(len))
+
+    }
+
+}
+
+impl AsyncSeek for AsyncMp3Stream {
+
+    fn poll_seek(self: Pin<&mut Self>, cx: &mut Context<'_>, pos: SeekFrom) -> Poll<io::Result<u64>> {
+
+        let this = self.get_mut();
+
+        let target = match pos {
+            SeekFrom::Start(val) => Some(val),
+            SeekFrom::Current(val) => this.position().checked_add_signed(val),
+            SeekFrom::End(val) if this.len > 0 => this.len.checked_add_signed(val),
+            SeekFrom::End(..) => return Poll::Ready(Err(io::Error::new(io::ErrorKind::Unsupported, "length of the track is unknown"))),
+        };
+
+        match target {
+            Some(val) => this.poll_seek_to(cx, val),
+            None => Poll::Ready(Err(io::Error::new(io::ErrorKind::InvalidInput, "invalid seek to a negative position"))),
+        }
+
+    }
+
+}
+
+impl Stream for AsyncMp3Stream {
+
+    type Item = io::Result<Vec<u8>>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+
+        let this = self.get_mut();
+
+        if this.ended {
+            return Poll::Ready(None)
+        }
+
+        let grant = ready!(this.throttle.poll_take(cx, CHUNK_SIZE));
+
+        let data = match this.poll_chunk(cx) {
+            Poll::Ready(Ok([])) => None,
+            Poll::Ready(Ok(data)) => Some(Ok(data[..data.len().min(grant)].to_vec())),
+            Poll::Ready(Err(err)) => Some(Err(err)),
+            Poll::Pending => {
+                this.throttle.refund(grant);
+                return Poll::Pending
+            },
+        };
+
+        let len = match &data { Some(Ok(data)) => data.len(), _ => 0 };
+        this.ended = len == 0;
+        this.throttle.refund(grant - len);
+        this.consume(len);
+
+        Poll::Ready(data)
+
+    }
+
+}
+
+/// The decrypted mp3 data of a track, this is a blocking adapter
+/// over `AsyncMp3Stream`.
+pub struct Mp3Stream {
+    inner: BlockOn<AsyncMp3Stream>, // we can just use BlockOn since rtv provides it's own reactor
+}
+
+impl Mp3Stream {
+
+    pub(crate) fn new(inner: AsyncMp3Stream) -> Self {
+        Self { inner: BlockOn::new(inner) }
+    }
+
+    /// See `AsyncMp3Stream::report_to`.
+    pub fn report_to(self, reporter: &ListenReporter, context: ListenContext) -> Self {
+        Self::new(self.inner.into_inner().report_to(reporter, context))
+    }
+
+    /// See `AsyncMp3Stream::played`.
+    pub fn played(&mut self, seconds: u64) {
+        self.inner.get_mut().played(seconds)
+    }
+
+    /// See `AsyncMp3Stream::throttle`.
+    pub fn throttle(self, limiter: &RateLimiter) -> Self {
+        Self::new(self.inner.into_inner().throttle(limiter))
+    }
+
+    pub fn into_async(self) -> AsyncMp3Stream {
+        self.inner.into_inner()
+    }
+
+}
+
+impl Read for Mp3Stream {
+
+    fn read(&mut self, buff: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(buff)
+    }
+
+}
+
+impl Seek for Mp3Stream {
+
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        future::block_on(self.inner.get_mut().seek(pos))
+    }
+
+}
+
+/// A truncated response shouldn't look like the end of the track.
+pub(crate) fn check_complete(position: u64, len: u64) -> io::Result<()> {
+    if position < len {
+        let msg = format!("track ended after {} of {} bytes", position, len);
+        return Err(io::Error::new(io::ErrorKind::UnexpectedEof, msg))
+    }
+    Ok(())
+}
+
+/// Encrypts one full 2048 byte chunk.
+fn encrypt_chunk(blowfish: &blowfish::Blowfish, chunk: &mut [u8]) {
+    let mut cbc_xor = *b"\x00\x01\x02\x03\x04\x05\x06\x07"; // magic iv
+    for block in chunk.chunks_exact_mut(8) {
+        zip(block.iter_mut(), cbc_xor).for_each(|(byte, val)| *byte ^= val);
+        blowfish.encrypt_block(GenericArray::from_mut_slice(block));
+        cbc_xor.copy_from_slice(block);
+    }
+}
+
+/// Decrypts one full 2048 byte chunk.
+fn decrypt_chunk(blowfish: &blowfish::Blowfish, chunk: &mut [u8]) {
+    // note: this is a manual implementation of blowfish cbc mode
+    // (took way too long to figure out)
+    let mut cbc_xor = *b"\x00\x01\x02\x03\x04\x05\x06\x07"; // magic iv
+    let mut block_copy = [0; 8];
+    for block in chunk.chunks_exact_mut(8) {
+        block_copy.copy_from_slice(block);
+        blowfish.decrypt_block(GenericArray::from_mut_slice(block));
+        zip(block.iter_mut(), cbc_xor).for_each(|(byte, val)| *byte ^= val);
+        cbc_xor = block_copy;
+    }
+}
+
 #[cfg(feature = "decode")]
 pub struct RawStream {
     decoder: minimp3::Decoder<Mp3Stream>,
@@ -237,32 +941,3 @@ impl Read for RawStream {
     }    
 
 }
-
-/// Basically the implementation from std but modified to return the number
-/// of bytes that were read on EOF.
-fn try_read_exact<R: Read>(mut this: R, mut buff: &mut [u8]) -> ReadExact {
-    let original_len = buff.len();
-    while !buff.is_empty() {
-        match this.read(buff) {
-            Ok(0) => break,
-            Ok(bytes_read) => {
-                let temp = buff;
-                buff = &mut temp[bytes_read..];
-            }
-            Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
-            Err(err) => return ReadExact::Err(err),
-        }
-    }
-    if !buff.is_empty() {
-        ReadExact::Eof(original_len - buff.len())
-    } else {
-        ReadExact::Ok
-    }
-}
-
-enum ReadExact {
-    Ok,
-    Eof(usize),
-    Err(io::Error),
-}
-
@@ -0,0 +1,560 @@
+
+use std::{fs, io::{self, Read, Seek, SeekFrom}, path::{Path, PathBuf}, pin::Pin, future::Future, task::Poll, sync::{Arc, Mutex, PoisonError, atomic::{AtomicU64, Ordering}}};
+
+use blocking::{unblock, Unblock};
+use futures_lite::{AsyncRead, AsyncReadExt, AsyncWriteExt, future};
+use serde_derive::{Serialize, Deserialize};
+
+use crate::{Track, TrackId, Format, Error, TrackDecryptor, RateLimiter, decrypt::{open_track, check_range, CdnUrl, CHUNK_SIZE}, throttle::{Throttle, Throttled}};
+
+/// How often the progress file is updated.
+const SAVE_INTERVAL: u64 = 1024 * 1024;
+
+/// Options for `Session::download_to`.
+#[derive(Debug, Clone, Default)]
+pub struct DownloadOptions {
+    pub format: Format,
+    /// Fail if the file doesn't have the size deezer lists for the format.
+    pub verify_size: bool,
+    /// Fail if the md5 checksum of the file (as hex) doesn't match.
+    pub md5: Option<String>,
+    /// Download this many parts of the track at once, each one is written into the file
+    /// at its own offset and resumed on its own. See `Session::download_segmented`.
+    /// Only used if the size of the track is known, zero or one means a single connection.
+    pub connections: usize,
+    /// Shared by all downloads using these options.
+    pub rate_limit: Option<RateLimiter>,
+    /// Every download gets its own limiter with this rate, see `RateLimiter::independent`.
+    pub rate_limit_each: Option<RateLimiter>,
+}
+
+impl DownloadOptions {
+
+    fn throttle(&self) -> Throttle {
+        let mut throttle = Throttle::default();
+        if let Some(limiter) = &self.rate_limit {
+            throttle.add(limiter.clone());
+        }
+        if let Some(limiter) = &self.rate_limit_each {
+            throttle.add(limiter.independent());
+        }
+        throttle
+    }
+
+}
+
+/// What a `.part` file is a download of. If any of it changed, deezer re-encoded the track
+/// and the old bytes can't be used anymore.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct PartKey {
+    track: TrackId,
+    format: Format,
+    media_version: u64,
+    size: Option<u64>,
+}
+
+impl PartKey {
+
+    pub(crate) fn new(track: &Track, format: Format) -> Self {
+        Self { track: track.id, format, media_version: track.media_version, size: track.size(format) }
+    }
+
+}
+
+/// What is stored in the `.part.json` file next to an unfinished download.
+#[derive(Debug, Serialize, Deserialize)]
+struct Progress {
+    #[serde(flatten)]
+    key: PartKey,
+    /// How many bytes at the start of the `.part` file are complete.
+    written: u64,
+    /// Where every part of a segmented download got to.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    segments: Vec<SegmentProgress>,
+}
+
+/// One part of a segmented download, see `split_segments`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct SegmentProgress {
+    pub(crate) start: u64,
+    pub(crate) end: u64,
+    /// How many bytes of this segment are on disk.
+    pub(crate) written: u64,
+}
+
+/// `progress` is called with the size of the file whenever something was written, an error aborts the download.
+pub(crate) async fn download(client: &mut rtv::SimpleClient, track: &Track, path: &Path, options: &DownloadOptions, progress: &mut (dyn FnMut(u64) -> Result<(), Error> + Send)) -> Result<u64, Error> {
+
+    let size = track.size(options.format);
+    if let (Some(size), 2..) = (size, options.connections) {
+
+        let (part, segments) = PartFile::open_segmented(path, PartKey::new(track, options.format), size, options.connections).await?;
+        part.write_segments(track, size, segments, options.throttle(), progress).await?;
+
+        // the segments arrive out of order, so the file is hashed at the end
+        let hasher = match options.md5 {
+            Some(..) => Some(part.hash_prefix(size).await?),
+            None => None,
+        };
+
+        return part.finish(track, options, size, hasher).await
+
+    }
+
+    let (mut part, offset) = PartFile::open(path, PartKey::new(track, options.format)).await?;
+
+    // the part that was already downloaded has to be hashed as well
+    let mut hasher = match options.md5 {
+        Some(..) => Some(part.hash_prefix(offset).await?),
+        None => None,
+    };
+
+    // asking for a range after the end of the track only gets a 416
+    if Some(offset) == size {
+        return part.finish(track, options, offset, hasher).await
+    }
+
+    let mut stream = open_track(client, track, options.format, offset).await?;
+    let stream = Throttled { reader: &mut stream, throttle: options.throttle() };
+
+    let size = part.write_from(stream, offset, &mut hasher, progress).await?;
+    part.finish(track, options, size, hasher).await
+
+}
+
+/// The `.part` file of an unfinished download and the `.part.json` file next to it.
+/// All file system access happens on the `blocking` thread pool.
+pub(crate) struct PartFile {
+    file: Unblock<fs::File>,
+    part_path: PathBuf,
+    progress_path: PathBuf,
+    path: PathBuf,
+    key: PartKey,
+}
+
+impl PartFile {
+
+    /// Open the `.part` file for `path` and find out where to resume.
+    /// Returns the file and the offset to continue at, it's always on a chunk boundary.
+    pub(crate) async fn open(path: &Path, key: PartKey) -> Result<(Self, u64), Error> {
+
+        let part_path = with_suffix(path, ".part");
+        let progress_path = with_suffix(path, ".part.json");
+
+        let (file, offset) = {
+            let part_path = part_path.clone();
+            let progress_path = progress_path.clone();
+            unblock(move || -> Result<_, Error> {
+
+                let mut file = fs::OpenOptions::new().create(true).write(true).truncate(false).open(&part_path)?;
+
+                let offset = match read_progress(&progress_path) {
+                    Some(progress) if progress.key == key => {
+                        let len = file.metadata()?.len().min(progress.written);
+                        len - len % CHUNK_SIZE as u64
+                    },
+                    _ => 0,
+                };
+
+                file.set_len(offset)?;
+                file.seek(SeekFrom::Start(offset))?;
+                write_progress(&progress_path, &Progress { key, written: offset, segments: Vec::new() })?;
+
+                Ok((file, offset))
+
+            }).await?
+        };
+
+        Ok((Self { file: Unblock::new(file), part_path, progress_path, path: path.to_path_buf(), key }, offset))
+
+    }
+
+    /// Like `open`, but for a download of `size` bytes that is split into `connections` segments.
+    /// The file is extended to the full size and the segments that were saved are continued.
+    pub(crate) async fn open_segmented(path: &Path, key: PartKey, size: u64, connections: usize) -> Result<(Self, Vec<SegmentProgress>), Error> {
+
+        let part_path = with_suffix(path, ".part");
+        let progress_path = with_suffix(path, ".part.json");
+
+        let (file, segments) = {
+            let part_path = part_path.clone();
+            let progress_path = progress_path.clone();
+            unblock(move || -> Result<_, Error> {
+
+                let file = fs::OpenOptions::new().create(true).write(true).truncate(false).open(&part_path)?;
+                let len = file.metadata()?.len();
+
+                let segments = match read_progress(&progress_path) {
+                    Some(progress) if progress.key == key && !progress.segments.is_empty()
+                        && progress.segments.iter().all(|it| it.end <= size) => progress.segments,
+                    Some(progress) if progress.key == key => {
+                        let written = len.min(progress.written);
+                        split_segments(written - written % CHUNK_SIZE as u64, size, connections)
+                    },
+                    _ => split_segments(0, size, connections),
+                };
+
+                // an unfinished segment can only continue on a chunk boundary
+                let segments: Vec<_> = segments.into_iter().map(|mut segment| {
+                    if segment.start + segment.written < segment.end {
+                        segment.written -= segment.written % CHUNK_SIZE as u64;
+                    }
+                    segment
+                }).collect();
+
+                file.set_len(size)?;
+                write_progress(&progress_path, &Progress { key, written: contiguous(&segments, size), segments: segments.clone() })?;
+
+                Ok((file, segments))
+
+            }).await?
+        };
+
+        Ok((Self { file: Unblock::new(file), part_path, progress_path, path: path.to_path_buf(), key }, segments))
+
+    }
+
+    /// Hash the first `len` bytes of the file.
+    pub(crate) async fn hash_prefix(&self, len: u64) -> Result<md5::Context, Error> {
+        let part_path = self.part_path.clone();
+        unblock(move || hash_prefix(&part_path, len)).await
+    }
+
+    /// Append everything from `reader` to the file, which is `offset` bytes long.
+    /// The progress is saved every now and then, so the download can be resumed if the reader fails.
+    pub(crate) async fn write_from<R: AsyncRead + Unpin>(&mut self, reader: R, offset: u64, hasher: &mut Option<md5::Context>, progress: &mut (dyn FnMut(u64) -> Result<(), Error> + Send)) -> Result<u64, Error> {
+
+        let progress_path = self.progress_path.clone();
+        let key = self.key;
+
+        let wrote = |offset, data: &[u8]| {
+            if let Some(hasher) = hasher {
+                hasher.consume(data);
+            }
+            progress(offset)
+        };
+
+        let save = |written| {
+            let progress_path = progress_path.clone();
+            unblock(move || write_progress(&progress_path, &Progress { key, written, segments: Vec::new() }))
+        };
+
+        copy_to_file(reader, &mut self.file, offset, u64::MAX, wrote, save).await
+
+    }
+
+    /// Download the missing parts of all `segments` at once. Every segment is written into the file at its own
+    /// offset and its progress is saved every now and then. The `throttle` limits all connections together.
+    async fn write_segments(&self, track: &Track, size: u64, segments: Vec<SegmentProgress>, throttle: Throttle, progress: &mut (dyn FnMut(u64) -> Result<(), Error> + Send)) -> Result<(), Error> {
+
+        let url = CdnUrl::new(track, self.key.format)?;
+
+        let shared = Arc::new(SharedProgress {
+            part_path: self.part_path.clone(),
+            progress_path: self.progress_path.clone(),
+            size,
+            total: AtomicU64::new(segments.iter().map(|it| it.written).sum()),
+            progress: Mutex::new(Progress { key: self.key, written: contiguous(&segments, size), segments: segments.clone() }),
+        });
+
+        let pending: Vec<Segment<()>> = segments.into_iter().enumerate()
+            .filter(|(_, segment)| segment.start + segment.written < segment.end)
+            .map(|(index, segment)| Box::pin(write_segment(track.id, url.clone(), Arc::clone(&shared), index, segment, throttle.clone())) as Segment<()>)
+            .collect();
+
+        let mut reported = None;
+        join_segments(pending, || {
+            let bytes = shared.total.load(Ordering::Relaxed);
+            if reported != Some(bytes) {
+                reported = Some(bytes);
+                progress(bytes)?;
+            }
+            Ok(())
+        }).await?;
+
+        Ok(())
+
+    }
+
+    /// Verify the file and move it to its final path.
+    pub(crate) async fn finish(mut self, track: &Track, options: &DownloadOptions, size: u64, hasher: Option<md5::Context>) -> Result<u64, Error> {
+
+        self.file.flush().await?;
+        let file = self.file.into_inner().await;
+        let verified = verify(track, options, size, hasher);
+
+        unblock(move || {
+
+            file.sync_all()?;
+            drop(file);
+
+            // a broken file can't be resumed, so it's thrown away
+            if let Err(err) = verified {
+                let _ = fs::remove_file(&self.part_path);
+                let _ = fs::remove_file(&self.progress_path);
+                return Err(err)
+            }
+
+            fs::rename(&self.part_path, &self.path)?;
+            let _ = fs::remove_file(&self.progress_path);
+
+            Ok(size)
+
+        }).await
+
+    }
+
+}
+
+/// Shared by all segments of a download, see `PartFile::write_segments`.
+struct SharedProgress {
+    part_path: PathBuf,
+    progress_path: PathBuf,
+    size: u64,
+    /// How many bytes all segments wrote together.
+    total: AtomicU64,
+    progress: Mutex<Progress>,
+}
+
+impl SharedProgress {
+
+    /// Remember that `written` bytes of segment `index` are on disk.
+    async fn save(self: &Arc<Self>, index: usize, written: u64) -> Result<(), Error> {
+        let shared = Arc::clone(self);
+        unblock(move || {
+            // held while writing, so the segments don't write the progress file at the same time
+            let mut progress = shared.progress.lock().unwrap_or_else(PoisonError::into_inner);
+            progress.segments[index].written = written;
+            progress.written = contiguous(&progress.segments, shared.size);
+            write_progress(&shared.progress_path, &progress)
+        }).await
+    }
+
+}
+
+type Segment<T> = Pin<Box<dyn Future<Output = Result<T, Error>> + Send>>;
+
+/// Split the bytes from `offset` to `size` into at most `connections` segments.
+/// Every segment starts on a chunk boundary, so it can be decrypted on its own.
+pub(crate) fn split_segments(offset: u64, size: u64, connections: usize) -> Vec<SegmentProgress> {
+
+    let remaining = size.saturating_sub(offset);
+    let per_connection = remaining.div_ceil(connections.max(1) as u64);
+    let segment_len = per_connection.div_ceil(CHUNK_SIZE as u64).max(1) * CHUNK_SIZE as u64;
+
+    let mut segments = Vec::new();
+    let mut start = offset;
+    while start < size {
+        let end = (start + segment_len).min(size);
+        segments.push(SegmentProgress { start, end, written: 0 });
+        start = end;
+    }
+
+    segments
+
+}
+
+/// How many bytes at the start of the file are complete, the segments are in order.
+fn contiguous(segments: &[SegmentProgress], size: u64) -> u64 {
+    for segment in segments {
+        if segment.start + segment.written < segment.end {
+            return segment.start + segment.written
+        }
+    }
+    size
+}
+
+/// Poll all segments until every one of them is done, `tick` is called whenever one of them made progress.
+async fn join_segments<T>(mut segments: Vec<Segment<T>>, mut tick: impl FnMut() -> Result<(), Error>) -> Result<Vec<T>, Error> {
+
+    let mut results: Vec<Option<T>> = segments.iter().map(|_| None).collect();
+    future::poll_fn(|cx| {
+        for (segment, result) in segments.iter_mut().zip(results.iter_mut()) {
+            if result.is_none() {
+                if let Poll::Ready(data) = segment.as_mut().poll(cx) {
+                    *result = Some(data?);
+                }
+            }
+        }
+        tick()?;
+        match results.iter().all(Option::is_some) {
+            true => Poll::Ready(Ok::<(), Error>(())),
+            false => Poll::Pending,
+        }
+    }).await?;
+
+    Ok(results.into_iter().flatten().collect())
+
+}
+
+/// Download a whole track of `size` bytes into memory using several connections at once,
+/// see `Session::download_segmented`. The `throttle` limits all connections together.
+pub(crate) async fn download_segmented(track: &Track, format: Format, size: u64, connections: usize, throttle: Throttle) -> Result<Vec<u8>, Error> {
+
+    let url = CdnUrl::new(track, format)?;
+
+    let segments: Vec<Segment<Vec<u8>>> = split_segments(0, size, connections).into_iter()
+        .map(|segment| Box::pin(fetch_range(track.id, url.clone(), segment.start, segment.end, throttle.clone())) as Segment<Vec<u8>>)
+        .collect();
+
+    Ok(join_segments(segments, || Ok(())).await?.concat())
+
+}
+
+/// Download and decrypt the bytes from `start` to `end` into memory.
+async fn fetch_range(track: TrackId, url: CdnUrl, start: u64, end: u64, throttle: Throttle) -> Result<Vec<u8>, Error> {
+
+    let (_client, mut reader) = open_range(track, &url, start, end, throttle).await?;
+
+    let mut data = Vec::with_capacity((end - start) as usize);
+    reader.read_to_end(&mut data).await?;
+
+    if data.len() as u64 != end - start {
+        return Err(Error::SizeMismatch(format!("expected {} bytes from {} to {}, got {}", end - start, start, end, data.len())))
+    }
+
+    Ok(data)
+
+}
+
+/// Download one segment into its part of the file, see `PartFile::write_segments`.
+async fn write_segment(track: TrackId, url: CdnUrl, shared: Arc<SharedProgress>, index: usize, segment: SegmentProgress, throttle: Throttle) -> Result<(), Error> {
+
+    let position = segment.start + segment.written;
+    let (_client, reader) = open_range(track, &url, position, segment.end, throttle).await?;
+
+    let file = {
+        let part_path = shared.part_path.clone();
+        unblock(move || -> io::Result<_> {
+            let mut file = fs::OpenOptions::new().write(true).open(&part_path)?;
+            file.seek(SeekFrom::Start(position))?;
+            Ok(file)
+        }).await?
+    };
+    let mut file = Unblock::new(file);
+
+    let wrote = |_, data: &[u8]| {
+        shared.total.fetch_add(data.len() as u64, Ordering::Relaxed);
+        Ok(())
+    };
+
+    let save = |position| {
+        let shared = Arc::clone(&shared);
+        async move { shared.save(index, position - segment.start).await }
+    };
+
+    let position = copy_to_file(reader, &mut file, position, segment.end, wrote, save).await?;
+
+    if position != segment.end {
+        return Err(Error::SizeMismatch(format!("expected {} bytes from {} to {}, got {}", segment.end - segment.start, segment.start, segment.end, position - segment.start)))
+    }
+
+    Ok(())
+
+}
+
+/// Copy everything from `reader` into `file`, which is at `position`, but not past `end`.
+/// `wrote` is called with the new position and the data after every write. Every now and then
+/// and once `end` is reached, the file is synced and `save` is called with the position,
+/// so the download can be resumed from there.
+async fn copy_to_file<R, F>(mut reader: R, file: &mut Unblock<fs::File>, mut position: u64, end: u64, mut wrote: impl FnMut(u64, &[u8]) -> Result<(), Error>, mut save: impl FnMut(u64) -> F) -> Result<u64, Error>
+where R: AsyncRead + Unpin, F: Future<Output = Result<(), Error>> {
+
+    let mut buff = vec![0; 64 * 1024];
+    let mut last_save = position;
+    loop {
+
+        let bytes_read = reader.read(&mut buff).await?;
+        if bytes_read == 0 { break }
+
+        // too much data would overwrite whatever comes next
+        if position + bytes_read as u64 > end {
+            return Err(Error::SizeMismatch(format!("got more data than expected, it should end at {}", end)))
+        }
+
+        file.write_all(&buff[..bytes_read]).await?;
+        position += bytes_read as u64;
+
+        wrote(position, &buff[..bytes_read])?;
+
+        if position - last_save >= SAVE_INTERVAL || position == end {
+            // the progress file must never claim more than is on disk
+            file.flush().await?;
+            file.with_mut(|file| file.sync_data()).await?;
+            save(position).await?;
+            last_save = position;
+        }
+
+    }
+
+    Ok(position)
+
+}
+
+/// Request the bytes from `start` to `end` using a new connection, the client has to be kept
+/// around while reading.
+async fn open_range(track: TrackId, url: &CdnUrl, start: u64, end: u64, throttle: Throttle) -> Result<(rtv::SimpleClient, Throttled<TrackDecryptor<rtv::BodyReader>>), Error> {
+
+    let mut client = rtv::SimpleClient::new()?;
+
+    let range = format!("bytes={}-{}", start, end - 1);
+    let req = rtv::Request::get().secure()
+        .host(&url.host)
+        .path(&url.path)
+        .set("Range", &range);
+
+    let resp = client.stream(req).await?;
+    check_range(&resp.head, start)?;
+
+    let reader = TrackDecryptor::for_track(resp.body, track).starting_at(start);
+    Ok((client, Throttled { reader, throttle }))
+
+}
+
+fn verify(track: &Track, options: &DownloadOptions, size: u64, hasher: Option<md5::Context>) -> Result<(), Error> {
+
+    if options.verify_size {
+        if let Some(expected) = track.size(options.format) {
+            if size != expected {
+                return Err(Error::SizeMismatch(format!("expected {} bytes, got {}", expected, size)))
+            }
+        }
+    }
+
+    if let (Some(expected), Some(hasher)) = (&options.md5, hasher) {
+        let actual = format!("{:x}", hasher.compute());
+        if !actual.eq_ignore_ascii_case(expected) {
+            return Err(Error::ChecksumMismatch(format!("expected {}, got {}", expected, actual)))
+        }
+    }
+
+    Ok(())
+
+}
+
+/// Hash the first `len` bytes of a file.
+fn hash_prefix(path: &Path, len: u64) -> Result<md5::Context, Error> {
+    let mut hasher = md5::Context::new();
+    let mut file = fs::File::open(path)?.take(len);
+    io::copy(&mut file, &mut hasher)?;
+    Ok(hasher)
+}
+
+fn read_progress(path: &Path) -> Option<Progress> {
+    let data = fs::read(path).ok()?;
+    serde_json::from_slice(&data).ok()
+}
+
+/// Written to a temporary file and renamed, so it's never half written.
+fn write_progress(path: &Path, progress: &Progress) -> Result<(), Error> {
+    let temp_path = with_suffix(path, ".tmp");
+    fs::write(&temp_path, serde_json::to_vec(progress)?)?;
+    fs::rename(&temp_path, path)?;
+    Ok(())
+}
+
+pub(crate) fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_os_string();
+    name.push(suffix);
+    PathBuf::from(name)
+}
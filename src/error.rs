@@ -7,6 +7,16 @@ pub enum Error {
     InvalidResponse(serde_json::Error),
     UnknownInvalidResponse,
     InvalidCredentials,
+    NotFound(String),
+    ApiError(String),
+    InvalidUrl(String),
+    NotOwner,
+    SizeMismatch(String),
+    ChecksumMismatch(String),
+    Cancelled,
+    HttpError(String),
+    /// Something went really wrong while downloading, see `DownloadManager`.
+    Panicked(String),
 }
 
 impl fmt::Display for Error {
@@ -16,12 +26,33 @@ impl fmt::Display for Error {
             Self::InvalidResponse(value) => write!(f, "InvalidResponse: {}", value),
             Self::UnknownInvalidResponse => write!(f, "UnknownInvalidResponse"), // todo: what the fuck is this?
             Self::InvalidCredentials => write!(f, "InvalidCredentials"),
+            Self::NotFound(value) => write!(f, "NotFound: {}", value),
+            Self::ApiError(value) => write!(f, "ApiError: {}", value),
+            Self::InvalidUrl(value) => write!(f, "InvalidUrl: {}", value),
+            Self::NotOwner => write!(f, "NotOwner"),
+            Self::SizeMismatch(value) => write!(f, "SizeMismatch: {}", value),
+            Self::ChecksumMismatch(value) => write!(f, "ChecksumMismatch: {}", value),
+            Self::Cancelled => write!(f, "Cancelled"),
+            Self::HttpError(value) => write!(f, "HttpError: {}", value),
+            Self::Panicked(value) => write!(f, "Panicked: {}", value),
         }
     }
 }
 
 impl std::error::Error for Error {}
 
+impl Error {
+
+    /// Replace the message of a `NotFound` error with a more descriptive one.
+    pub(crate) fn not_found(self, msg: String) -> Self {
+        match self {
+            Self::NotFound(..) => Self::NotFound(msg),
+            other => other,
+        }
+    }
+
+}
+
 impl From<io::Error> for Error {
     fn from(value: io::Error) -> Self {
         Self::IoError(value)
@@ -0,0 +1,92 @@
+
+use std::{fmt, num::ParseIntError, str::FromStr};
+
+use serde::{de::{self, Visitor}, Deserialize, Deserializer, Serialize, Serializer};
+
+macro_rules! ids {
+    ($($(#[$meta:meta])* $name:ident),* $(,)?) => {$(
+
+        $(#[$meta])*
+        #[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+        pub struct $name(pub u64);
+
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                write!(f, "{}", self.0)
+            }
+        }
+
+        impl FromStr for $name {
+            type Err = ParseIntError;
+            fn from_str(value: &str) -> Result<Self, Self::Err> {
+                Ok(Self(value.parse()?))
+            }
+        }
+
+        impl From<u64> for $name {
+            fn from(value: u64) -> Self {
+                Self(value)
+            }
+        }
+
+        impl Serialize for $name {
+            fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                serializer.serialize_u64(self.0)
+            }
+        }
+
+        impl<'de> Deserialize<'de> for $name {
+            fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                deserializer.deserialize_any(IdVisitor).map(Self)
+            }
+        }
+
+    )*};
+}
+
+ids! {
+    /// The id of a `Track`.
+    TrackId,
+    /// The id of an `Album`.
+    AlbumId,
+    /// The id of an `Artist`.
+    ArtistId,
+    /// The id of a `Playlist`.
+    PlaylistId,
+    /// The id of a `User` or `SmallUser`.
+    UserId,
+    /// The id of a `Radio`.
+    RadioId,
+    /// The id of a `Genre`, `GenreId(0)` means all genres.
+    GenreId,
+    /// The id of a podcast `Show`.
+    ShowId,
+    /// The id of a podcast `Episode`.
+    EpisodeId,
+}
+
+/// Deezer sends ids as strings in the private api but as numbers
+/// in the public one, so accept both.
+struct IdVisitor;
+
+impl<'de> Visitor<'de> for IdVisitor {
+
+    type Value = u64;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "an id, either as a number or as a string")
+    }
+
+    fn visit_u64<E: de::Error>(self, value: u64) -> Result<Self::Value, E> {
+        Ok(value)
+    }
+
+    fn visit_i64<E: de::Error>(self, value: i64) -> Result<Self::Value, E> {
+        u64::try_from(value).map_err(|_| E::invalid_value(de::Unexpected::Signed(value), &self))
+    }
+
+    fn visit_str<E: de::Error>(self, value: &str) -> Result<Self::Value, E> {
+        value.parse().map_err(|_| E::invalid_value(de::Unexpected::Str(value), &self))
+    }
+
+}
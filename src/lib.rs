@@ -2,6 +2,10 @@
 /*
 * Interface to deezers public and private API.
 * This library allows downloading songs, discovering music, searching etc.
+*
+* All model types implement `Serialize`. They serialize using their snake_case
+* field names (not deezers SHOUTY ones) and deserialize from both forms, so
+* serialized values can be cached and read back in.
 */
 
 #[cfg(test)]
@@ -9,13 +13,28 @@ mod test;
 
 mod error;
 mod decrypt;
-
-use serde_derive::Deserialize;
+mod url;
+mod id;
+mod listen;
+mod download;
+mod manager;
+mod throttle;
+
+use serde_derive::{Serialize, Deserialize};
 use serde::{de::{DeserializeOwned, Deserialize}, Deserializer};
 use serde_json::{Value as JsonValue, json};
+use futures_lite::{Stream, stream};
+
+use std::{collections::{VecDeque, HashSet}, path::Path, io};
 
 pub use error::Error;
 pub use decrypt::*;
+pub use url::*;
+pub use id::*;
+pub use listen::*;
+pub use download::DownloadOptions;
+pub use manager::*;
+pub use throttle::RateLimiter;
 
 #[derive(Debug, Default, Deserialize)]
 pub struct UserInfo {
@@ -29,6 +48,8 @@ pub struct Session {
     client: rtv::SimpleClient,
     middleware: Middleware,
     user: User,
+    /// The account that logged in, `user` changes when switching profiles.
+    main_user: UserId,
 }
 
 impl Session {
@@ -52,20 +73,66 @@ impl Session {
             client,
             middleware,
             user: User::default(),
+            main_user: UserId::default(),
         };
 
         // initial query with the only authentication being the sid and arl
-        let resp = session.gw_light_query("deezer.getUserData", json!({})).await?;
-        let user: User = Deserialize::deserialize(resp)?;
-
-        session.middleware.license_token = user.license_token.clone();
-        session.middleware.api_token = user.api_token.clone();
-        session.user = user;
+        session.bootstrap().await?;
+        session.main_user = session.user.id;
 
         Ok(session)
             
     }
 
+    /// Act as one of the family members listed in `UserFamily`.
+    /// All following requests will use the library of this profile.
+    pub async fn switch_profile(&mut self, profile: &SmallUser) -> Result<(), Error> {
+        self.switch_to(profile.id).await
+    }
+
+    /// Go back to the account that logged in.
+    pub async fn switch_to_main(&mut self) -> Result<(), Error> {
+        self.switch_to(self.main_user).await
+    }
+
+    /// If the session currently acts as the account that logged in.
+    pub fn is_main_profile(&self) -> bool {
+        self.user.id == self.main_user
+    }
+
+    async fn switch_to(&mut self, id: UserId) -> Result<(), Error> {
+
+        if id == self.user.id {
+            return Ok(())
+        }
+
+        self.gw_light_query("deezer.setChildAccount", json!({"child_id": id})).await?;
+
+        // the tokens are bound to the profile
+        self.bootstrap().await?;
+
+        if self.user.id != id {
+            return Err(Error::ApiError(format!("could not switch to profile {}", id)))
+        }
+
+        Ok(())
+
+    }
+
+    /// Fetch the current user and the tokens needed for all other requests.
+    async fn bootstrap(&mut self) -> Result<(), Error> {
+
+        let resp = self.gw_light_query("deezer.getUserData", json!({})).await?;
+        let user: User = Deserialize::deserialize(resp)?;
+
+        self.middleware.license_token = user.license_token.clone();
+        self.middleware.api_token = user.api_token.clone();
+        self.user = user;
+
+        Ok(())
+
+    }
+
     pub fn user(&self) -> Result<User, Error> {
         Ok(Clone::clone(&self.user))
     }
@@ -102,24 +169,553 @@ impl Session {
     }
 
     pub async fn stream_mp3<'d>(&'d mut self, track: &Track) -> Result<Mp3Stream, Error> {
+        let stream = self.stream_mp3_async(track).await?;
+        Ok(Mp3Stream::new(stream))
+    }
+
+    /// Like `stream_mp3` but reading doesn't block.
+    pub async fn stream_mp3_async(&mut self, track: &Track) -> Result<AsyncMp3Stream, Error> {
+        open_track(&mut self.client, track, Format::Mp3_128, 0).await
+    }
+
+    /// Download a track to a file.
+    ///
+    /// The data is written to `<path>.part` first, together with a `<path>.part.json` file
+    /// that describes the download. If a download is interrupted, calling this again
+    /// resumes it where it stopped. The file is renamed to `path` once it's complete
+    /// and passed the checks in `options`. Returns the size of the file.
+    pub async fn download_to(&mut self, track: &Track, path: impl AsRef<Path>, options: &DownloadOptions) -> Result<u64, Error> {
+        download::download(&mut self.client, track, path.as_ref(), options, &mut |_| Ok(())).await
+    }
+
+    /// Download a whole track into memory, fetching `connections` parts of it at once.
+    /// This is faster for large files over slow links, the parts are decrypted
+    /// on their own and put back together in order.
+    pub async fn download_segmented(&mut self, track: &Track, format: Format, connections: usize) -> Result<io::Cursor<Vec<u8>>, Error> {
+        let size = track.size(format).ok_or_else(|| Error::NotFound(format!("size of track {} in format {}", track.id, format.as_str())))?;
+        let data = download::download_segmented(track, format, size, connections, Default::default()).await?;
+        Ok(io::Cursor::new(data))
+    }
+
+    /// Tell deezer the user listened to a track, this is used for the Flow and the listening history.
+    pub async fn report_listen(&mut self, listen: &Listen) -> Result<(), Error> {
+        self.gw_light_query("log.listen", listen.to_json()).await?;
+        Ok(())
+    }
+
+    /// Send all listens queued in the reporter. Returns how many were sent.
+    /// If sending fails the remaining listens stay queued.
+    pub async fn flush_listens(&mut self, reporter: &ListenReporter) -> Result<usize, Error> {
+
+        let mut count = 0;
+        while let Some(listen) = reporter.pop() {
+            if let Err(err) = self.report_listen(&listen).await {
+                reporter.unpop(listen);
+                return Err(err)
+            }
+            count += 1;
+        }
+
+        Ok(count)
+
+    }
+
+
+    /// Fetch multiple tracks at once.
+    pub async fn tracks_by_ids(&mut self, ids: &[TrackId]) -> Result<Vec<Track>, Error> {
+
+        let result = self.gw_light_query("song.getListData", json!({"sng_ids": ids})).await?;
+        let tracks = des_after_data(result)?;
+
+        Ok(tracks)
+
+    }
+
+    /// Look up a track by its ISRC.
+    pub async fn track_by_isrc(&mut self, isrc: &str) -> Result<Track, Error> {
+
+        let result = self.public_query(&format!("/track/isrc:{}", isrc)).await
+            .map_err(|err| err.not_found(format!("no track with isrc {}", isrc)))?;
+
+        // the public api doesn't include the fields needed for streaming
+        let id = match result["id"].as_u64() { Some(val) => val, None => return Err(Error::UnknownInvalidResponse) };
+        let track = self.gw_light_query("song.getData", json!({"sng_id": id})).await?;
+
+        Ok(Deserialize::deserialize(track)?)
+
+    }
+
+    /// Look up an album by its UPC.
+    pub async fn album_by_upc(&mut self, upc: &str) -> Result<Album, Error> {
+
+        let result = self.public_query(&format!("/album/upc:{}", upc)).await
+            .map_err(|err| err.not_found(format!("no album with upc {}", upc)))?;
+
+        let id = match result["id"].as_u64() { Some(val) => val, None => return Err(Error::UnknownInvalidResponse) };
+        let album = self.gw_light_query("album.getData", json!({"alb_id": id})).await?;
+
+        Ok(Deserialize::deserialize(album)?)
+
+    }
+
+    /// Fetch the item a `DeezerId` refers to.
+    pub async fn resolve(&mut self, id: &DeezerId) -> Result<DeezerItem, Error> {
+
+        let item = match *id {
+            DeezerId::Track(id) => DeezerItem::Track(Deserialize::deserialize(
+                self.gw_light_query("song.getData", json!({"sng_id": id})).await?
+            )?),
+            DeezerId::Album(id) => DeezerItem::Album(Deserialize::deserialize(
+                self.gw_light_query("album.getData", json!({"alb_id": id})).await?
+            )?),
+            DeezerId::Artist(id) => DeezerItem::Artist(Deserialize::deserialize(
+                self.gw_light_query("artist.getData", json!({"art_id": id})).await?
+            )?),
+            DeezerId::Playlist(id) => DeezerItem::Playlist(Deserialize::deserialize(
+                self.gw_light_query("playlist.getData", json!({"playlist_id": id})).await?
+            )?),
+            DeezerId::User(id) => {
+                let mut result = self.gw_light_query("deezer.pageProfile", json!({"user_id": id, "tab": "loved", "nb": 0})).await?;
+                DeezerItem::User(Deserialize::deserialize(take_json(&mut result, "/DATA/USER"))?)
+            },
+        };
+
+        Ok(item)
+
+    }
+
+    /// List the users favorite tracks, albums, artists or playlists.
+    /// Use `favorites::<Track>` for the loved tracks.
+    pub async fn favorites<F: Favorite + DeserializeOwned>(&mut self, start: usize, nb: usize) -> Result<Page<F>, Error> {
+        self.profile_tab(self.user.id, F::TAB, start, nb).await
+    }
+
+    pub async fn add_favorite<F: Favorite>(&mut self, item: &F) -> Result<(), Error> {
+        let query = item.add_query();
+        self.gw_light_query(query.method, query.body).await?;
+        Ok(())
+    }
+
+    pub async fn remove_favorite<F: Favorite>(&mut self, item: &F) -> Result<(), Error> {
+        let query = item.remove_query();
+        self.gw_light_query(query.method, query.body).await?;
+        Ok(())
+    }
+
+    /// The users "Loved Tracks" playlist.
+    pub async fn loved_tracks(&mut self) -> Result<Playlist, Error> {
+        let result = self.gw_light_query("playlist.getData", json!({"playlist_id": self.user.loved_tracks})).await?;
+        Ok(Deserialize::deserialize(result)?)
+    }
+
+    /// The users Flow, an endless stream of personalized tracks.
+    /// Pass a mood or genre like `"chill"` to get one of the Flow variants.
+    pub fn flow(&mut self, config: Option<&str>) -> impl Stream<Item = Result<Track, Error>> + '_ {
+        self.radio(RadioSource::Flow(config.map(ToString::to_string)))
+    }
+
+    /// An endless stream of tracks, new batches are fetched once the current one runs out.
+    /// The stream ends after an error or if deezer has no more tracks.
+    pub fn radio(&mut self, source: RadioSource) -> impl Stream<Item = Result<Track, Error>> + '_ {
+
+        let state = (self, source, RadioQueue::default(), false);
+
+        stream::unfold(state, |(session, source, mut queue, done)| async move {
+
+            if done {
+                return None
+            }
+
+            // deezer often sends tracks we already had, so it might take a few tries
+            while queue.is_empty() {
+                let (method, body) = source.query(session.user.id);
+                let batch: Result<Vec<Track>, Error> = match session.gw_light_query(method, body).await {
+                    Ok(result) => des_after_data(result).map_err(Error::from),
+                    Err(err) => Err(err),
+                };
+                match batch {
+                    Ok(tracks) => if !queue.refill(tracks) { return None },
+                    Err(err) => return Some((Err(err), (session, source, queue, true))),
+                }
+            }
+
+            let track = queue.pop()?;
+            Some((Ok(track), (session, source, queue, false)))
+
+        })
+
+    }
+
+    /// The personalized home page.
+    pub async fn home(&mut self) -> Result<Channel, Error> {
+        self.page_query("home").await
+    }
+
+    /// A channel page, eg. `"rock"` or `"new-releases"`.
+    /// The names can be found in the `ChannelLink`s on the home page.
+    pub async fn channel(&mut self, name: &str) -> Result<Channel, Error> {
+        let name = name.trim_start_matches('/').trim_start_matches("channels/");
+        self.page_query(&format!("channels/{}", name)).await
+    }
+
+    async fn page_query(&mut self, page: &str) -> Result<Channel, Error> {
+
+        let kinds = ["album", "artist", "channel", "playlist", "track"];
+        let input = json!({
+            "PAGE": page,
+            "VERSION": "2.5",
+            "SUPPORT": {
+                "grid": kinds,
+                "horizontal-grid": kinds,
+                "large-card": kinds,
+                "item-highlight": kinds,
+                "slideshow": kinds,
+            },
+            "LANG": "en",
+        }).to_string();
+
+        let result = self.gw_light_query_with("page.get", &[("gateway_input", &input)], json!({})).await?;
+
+        Ok(Deserialize::deserialize(result)?)
+
+    }
+
+    /// The current charts for a genre, use `GenreId(0)` for all genres.
+    /// Deezer decides the country based on where the request comes from,
+    /// use `country_charts` for the charts of a specific country.
+    pub async fn chart(&mut self, genre: GenreId) -> Result<Chart, Error> {
+
+        let result = self.public_query(&format!("/chart/{}", genre)).await?;
+
+        // tracks need fields the public api doesn't have to be streamed, so they are fetched again
+        let track_ids: Vec<TrackId> = public_ids(&result["tracks"]["data"]);
+        let tracks = self.tracks_by_ids(&track_ids).await?;
+
+        let albums = public_items(&result["albums"]["data"], public_album);
+        let artists = public_items(&result["artists"]["data"], public_artist);
+        let playlists = public_items(&result["playlists"]["data"], public_playlist);
+
+        Ok(Chart { tracks, albums, artists, playlists })
+
+    }
+
+    /// The chart playlists of all countries. They are fetched all at once,
+    /// so pick the countries you need from the list and pass them to `country_chart`.
+    pub async fn country_charts(&mut self) -> Result<Vec<CountryChart>, Error> {
+
+        // the public api doesn't translate the titles, unlike the private one
+        let path = format!("/user/{}/playlists", CHARTS_USER);
+        let mut playlists = Vec::new();
+        loop {
+            let index = playlists.len().to_string();
+            let result = self.public_query_with(&path, &[("index", &index), ("limit", "100")]).await?;
+            let page: Vec<Playlist> = public_items(&result["data"], public_playlist);
+            let total = result["total"].as_u64().unwrap_or_default() as usize;
+            if page.is_empty() {
+                break
+            }
+            playlists.extend(page);
+            if playlists.len() >= total {
+                break
+            }
+        }
+
+        Ok(playlists.into_iter().filter_map(CountryChart::from_playlist).collect())
+
+    }
+
+    /// The top tracks of a country. Deezer only has per-country charts as playlists,
+    /// so the albums and artists are always empty and the playlist is the chart itself.
+    pub async fn country_chart(&mut self, chart: &CountryChart) -> Result<Chart, Error> {
+        let details: PlaylistDetails = self.details(&chart.playlist).await?;
+        Ok(Chart { tracks: details.tracks, albums: Vec::new(), artists: Vec::new(), playlists: vec![chart.playlist.clone()] })
+    }
+
+    /// All genres deezer has editorial pages for.
+    pub async fn genres(&mut self) -> Result<Vec<Genre>, Error> {
+        let result = self.public_query("/genre").await?;
+        Ok(des_after_data(result)?)
+    }
+
+    /// Albums picked by deezers editors for a genre.
+    pub async fn editorial_selection(&mut self, genre: GenreId) -> Result<Vec<Album>, Error> {
+        self.editorial_albums(&format!("/editorial/{}/selection", genre)).await
+    }
+
+    /// New releases picked by deezers editors for a genre.
+    pub async fn editorial_releases(&mut self, genre: GenreId) -> Result<Vec<Album>, Error> {
+        self.editorial_albums(&format!("/editorial/{}/releases", genre)).await
+    }
+
+    async fn editorial_albums(&mut self, path: &str) -> Result<Vec<Album>, Error> {
 
-        let song_quality = 1;
+        let result = self.public_query(path).await?;
 
-        let url_key = generate_url_key(track, song_quality);
-        let blowfish_key = generate_blowfish_key(track);
+        Ok(public_items(&result["data"], public_album))
 
-        let host = format!("e-cdns-proxy-{}.dzcdn.net", &track.md5_origin[0..1]);
-        let path = format!("/mobile/1/{}", url_key);
-        let req = rtv::Request::get().secure()
-            .host(&host)
-            .path(&path);
+    }
+
+    /// The public playlists of any user.
+    pub async fn user_playlists(&mut self, user: &SmallUser, start: usize, nb: usize) -> Result<Page<Playlist>, Error> {
+        self.profile_tab(user.id, "playlists", start, nb).await
+    }
+
+    /// The users following someone.
+    pub async fn followers(&mut self, user: &SmallUser, start: usize, nb: usize) -> Result<Page<SmallUser>, Error> {
+        self.profile_tab(user.id, "followers", start, nb).await
+    }
+
+    /// The users someone is following.
+    pub async fn followings(&mut self, user: &SmallUser, start: usize, nb: usize) -> Result<Page<SmallUser>, Error> {
+        self.profile_tab(user.id, "following", start, nb).await
+    }
+
+    pub async fn follow(&mut self, user: &SmallUser) -> Result<(), Error> {
+        self.gw_light_query("friend.follow", json!({"friend_id": user.id})).await?;
+        Ok(())
+    }
+
+    pub async fn unfollow(&mut self, user: &SmallUser) -> Result<(), Error> {
+        self.gw_light_query("friend.unfollow", json!({"friend_id": user.id})).await?;
+        Ok(())
+    }
+
+    /// Fetch one tab of a users profile page.
+    async fn profile_tab<T: DeserializeOwned>(&mut self, user: UserId, tab: &str, start: usize, nb: usize) -> Result<Page<T>, Error> {
 
-        let resp = self.client.stream(req).await?;
+        let mut result = self.gw_light_query("deezer.pageProfile", json!({
+            "user_id": user,
+            "tab": tab,
+            "start": start,
+            "nb": nb,
+        })).await?;
+
+        Page::from_response(take_json(&mut result, &format!("/TAB/{}", tab)), start)
+
+    }
+
+    /// Add an entry to the users search history.
+    pub async fn add_search_history(&mut self, entry: &SearchHistoryEntry) -> Result<(), Error> {
+        self.gw_light_query("user.addEntryInSearchHistory", json!({"ENTRY": entry.to_json()})).await?;
+        Ok(())
+    }
+
+    /// Remove a single entry from the users search history.
+    pub async fn remove_search_history(&mut self, entry: &SearchHistoryEntry) -> Result<(), Error> {
+        self.gw_light_query("user.removeEntryInSearchHistory", json!({"ENTRY": entry.to_json()})).await?;
+        Ok(())
+    }
+
+    pub async fn clear_search_history(&mut self) -> Result<(), Error> {
+        self.gw_light_query("user.clearSearchHistory", json!({"user_id": self.user.id})).await?;
+        Ok(())
+    }
+
+    /// The tracks the user listened to, most recent first.
+    pub async fn listening_history(&mut self, start: usize, nb: usize) -> Result<Page<HistoryEntry>, Error> {
+
+        let result = self.gw_light_query("user.getSongsHistory", json!({
+            "user_id": self.user.id,
+            "start": start,
+            "nb": nb,
+        })).await?;
+
+        Page::from_response(result, start)
+
+    }
+
+    /// The albums, playlists, artists and radios the user recently played, most recent first.
+    pub async fn recently_played(&mut self, start: usize, nb: usize) -> Result<Page<RecentItem>, Error> {
+
+        let result = self.gw_light_query("user.getRecentlyPlayed", json!({
+            "user_id": self.user.id,
+            "start": start,
+            "nb": nb,
+        })).await?;
 
-        Ok(Mp3Stream::new(resp.body, blowfish_key.as_bytes()))
+        Page::from_response(result, start)
 
     }
 
+    /// Create a new playlist owned by the user.
+    pub async fn create_playlist(&mut self, options: &PlaylistOptions) -> Result<Playlist, Error> {
+
+        let result = self.gw_light_query("playlist.create", json!({
+            "title": options.title,
+            "description": options.description,
+            "status": options.status(),
+            "songs": [],
+        })).await?;
+
+        let id: PlaylistId = Deserialize::deserialize(result)?;
+        let playlist = self.gw_light_query("playlist.getData", json!({"playlist_id": id})).await?;
+
+        Ok(Deserialize::deserialize(playlist)?)
+
+    }
+
+    /// Change the title, description and visibility of a playlist.
+    pub async fn update_playlist(&mut self, playlist: &Playlist, options: &PlaylistOptions) -> Result<(), Error> {
+
+        self.check_owner(playlist)?;
+
+        self.gw_light_query("playlist.update", json!({
+            "playlist_id": playlist.id,
+            "title": options.title,
+            "description": options.description,
+            "status": options.status(),
+        })).await?;
+
+        Ok(())
+
+    }
+
+    pub async fn delete_playlist(&mut self, playlist: &Playlist) -> Result<(), Error> {
+        self.check_owner(playlist)?;
+        self.gw_light_query("playlist.delete", json!({"playlist_id": playlist.id})).await?;
+        Ok(())
+    }
+
+    /// Add tracks to a playlist, at the end if `position` is `None`.
+    pub async fn add_to_playlist(&mut self, playlist: &Playlist, tracks: &[TrackId], position: Option<usize>) -> Result<(), Error> {
+
+        self.check_editor(playlist)?;
+
+        let songs: Vec<_> = tracks.iter().map(|id| json!([id, 0])).collect();
+        let offset = match position { Some(val) => json!(val), None => json!(-1) };
+
+        self.gw_light_query("playlist.addSongs", json!({
+            "playlist_id": playlist.id,
+            "songs": songs,
+            "offset": offset,
+        })).await?;
+
+        Ok(())
+
+    }
+
+    pub async fn remove_from_playlist(&mut self, playlist: &Playlist, tracks: &[TrackId]) -> Result<(), Error> {
+
+        self.check_editor(playlist)?;
+
+        let songs: Vec<_> = tracks.iter().map(|id| json!([id, 0])).collect();
+
+        self.gw_light_query("playlist.deleteSongs", json!({
+            "playlist_id": playlist.id,
+            "songs": songs,
+        })).await?;
+
+        Ok(())
+
+    }
+
+    /// Reorder a playlist, `order` has to contain all tracks of the playlist.
+    pub async fn reorder_playlist(&mut self, playlist: &Playlist, order: &[TrackId]) -> Result<(), Error> {
+
+        self.check_editor(playlist)?;
+
+        self.gw_light_query("playlist.updateOrder", json!({
+            "playlist_id": playlist.id,
+            "order": order,
+        })).await?;
+
+        Ok(())
+
+    }
+
+    /// Upload a custom cover for a playlist. The image has to be a jpeg.
+    pub async fn set_playlist_cover(&mut self, playlist: &Playlist, jpeg: &[u8]) -> Result<(), Error> {
+
+        self.check_owner(playlist)?;
+
+        let boundary = "----rizzle-cover-boundary";
+        let mut body = Vec::with_capacity(jpeg.len() + 256);
+        body.extend_from_slice(format!("--{}\r\n", boundary).as_bytes());
+        body.extend_from_slice(b"Content-Disposition: form-data; name=\"file\"; filename=\"cover.jpg\"\r\n");
+        body.extend_from_slice(b"Content-Type: image/jpeg\r\n\r\n");
+        body.extend_from_slice(jpeg);
+        body.extend_from_slice(format!("\r\n--{}--\r\n", boundary).as_bytes());
+
+        let path = format!("/playlist/{}", playlist.id);
+        let content_type = format!("multipart/form-data; boundary={}", boundary);
+        let req = rtv::Request::post().secure()
+            .host("upload.deezer.com")
+            .path(&path)
+            .query("api_token", &self.middleware.api_token)
+            .set("Content-Type", &content_type)
+            .send(&body);
+
+        let req = self.middleware.decorate(req);
+
+        let resp = self.client.send(req).await?;
+        let json: JsonValue = serde_json::from_slice(&resp.body)?;
+
+        if let Some(msg) = json["error"]["message"].as_str() {
+            return Err(Error::ApiError(msg.to_string()))
+        }
+
+        Ok(())
+
+    }
+
+    /// Everyone can change the tracks of a collaborative playlist, the rest is up to the owner.
+    fn check_editor(&self, playlist: &Playlist) -> Result<(), Error> {
+        if playlist.collaborative {
+            return Ok(())
+        }
+        self.check_owner(playlist)
+    }
+
+    /// Playlists that don't know their owner are let through, deezer
+    /// will reject the request if it isn't allowed.
+    fn check_owner(&self, playlist: &Playlist) -> Result<(), Error> {
+        if playlist.owner != UserId(0) && playlist.owner != self.user.id {
+            return Err(Error::NotOwner)
+        }
+        Ok(())
+    }
+
+    pub async fn episode(&mut self, id: EpisodeId) -> Result<Episode, Error> {
+        let result = self.gw_light_query("episode.getData", json!({"episode_id": id})).await?;
+        Ok(Deserialize::deserialize(result)?)
+    }
+
+    /// Stream the audio of a podcast episode.
+    /// Episodes are served from wherever the show is hosted, mostly as mp3.
+    pub async fn stream_episode(&mut self, episode: &Episode) -> Result<EpisodeStream, Error> {
+
+        // podcast urls mostly redirect through a few tracking services
+        let mut url = episode.url.clone();
+        for _ in 0..MAX_REDIRECTS {
+
+            let (secure, host, path) = url::split_url(&url)?;
+
+            let mut req = rtv::Request::get()
+                .host(host)
+                .path(&path);
+
+            if secure {
+                req = req.secure();
+            }
+
+            let resp = self.client.stream(req).await?;
+
+            let next = match resp.head.status.code {
+                200..=299 => return Ok(EpisodeStream::new(resp.body)),
+                300..=399 => match url::header(&resp.head, "Location") {
+                    Some(location) => url::resolve_location(secure, host, location)?,
+                    None => return Err(Error::HttpError(format!("redirect without a location from {}", url))),
+                },
+                code => return Err(Error::HttpError(format!("{} {} from {}", code, resp.head.status.text, url))),
+            };
+
+            url = next;
+
+        }
+
+        Err(Error::HttpError(format!("too many redirects for {}", episode.url)))
+
+    }
 
     #[cfg(feature = "decode")]
     pub async fn stream_raw<'d>(&'d mut self, track: &Track) -> Result<RawStream, Error> {
@@ -139,42 +735,106 @@ impl Session {
 
         let req = self.middleware.decorate(req);
 
-        let mut resp = JsonValue::from(self.client.send(req).await?.body);
+        let mut resp: JsonValue = serde_json::from_slice(&self.client.send(req).await?.body)?;
         
-        let result = resp["data"].take();
+        let result = take_json(&mut resp, "/data");
 
         Ok(result)
 
     }
 
+    /// Query the public api at `api.deezer.com`.
+    async fn public_query(&mut self, path: &str) -> Result<JsonValue, Error> {
+        self.public_query_with(path, &[]).await
+    }
+
+    /// Like `public_query` but with additional query parameters.
+    async fn public_query_with(&mut self, path: &str, params: &[(&str, &str)]) -> Result<JsonValue, Error> {
+
+        let mut req = rtv::Request::get().secure()
+            .host("api.deezer.com")
+            .path(path);
+
+        for (key, value) in params {
+            req = req.query(key, value);
+        }
+
+        let req = self.middleware.decorate(req);
+
+        let resp = self.client.send(req).await?;
+        let json: JsonValue = serde_json::from_slice(&resp.body)?;
+
+        if let Some(error) = json.get("error") {
+            let msg = error["message"].as_str().unwrap_or_default().to_string();
+            return match error["code"].as_u64() {
+                Some(800) => Err(Error::NotFound(msg)), // "no data"
+                _ => Err(Error::ApiError(msg)),
+            }
+        }
+
+        Ok(json)
+
+    }
+
     async fn gw_light_query(&mut self, method: &str, body: JsonValue) -> Result<JsonValue, Error> {
+        self.gw_light_query_with(method, &[], body).await
+    }
+
+    /// Like `gw_light_query` but with additional query parameters.
+    async fn gw_light_query_with(&mut self, method: &str, params: &[(&str, &str)], body: JsonValue) -> Result<JsonValue, Error> {
 
         let body_str = body.to_string();
-        let req = rtv::Request::post().secure()
+        let mut req = rtv::Request::post().secure()
             .host("www.deezer.com")
             .path("/ajax/gw-light.php")
             .query("method", method)
             .query("input", "3")
             .query("api_version", "1.0")
             .query("api_token", &self.middleware.api_token)
-            .query("cid", "94330654")
-            .send(&body_str);
+            .query("cid", "94330654");
+
+        for (key, value) in params {
+            req = req.query(key, value);
+        }
+
+        let req = req.send(&body_str);
 
         let req = self.middleware.decorate(req);
 
         let resp = self.client.send(req).await?;
         let mut json = serde_json::from_slice(&resp.body)?;
 
-        if Self::has_csrf_token_error(&json) {
-            return Err(Error::InvalidCredentials)
+        if let Some(err) = Self::gw_light_error(&json) {
+            return Err(err)
         }
 
-        let result = json["results"].take();
+        let result = take_json(&mut json, "/results");
 
         Ok(result)
 
     }
 
+    /// The error in a gw-light response, if there is one.
+    pub(crate) fn gw_light_error(json: &JsonValue) -> Option<Error> {
+
+        if Self::has_csrf_token_error(json) {
+            return Some(Error::InvalidCredentials)
+        }
+
+        if let Some(msg) = json["error"]["DATA_ERROR"].as_str() {
+            return Some(Error::NotFound(msg.to_string()))
+        }
+
+        // on success deezer sends an empty array here
+        if let Some((kind, msg)) = json["error"].as_object().and_then(|it| it.iter().next()) {
+            let msg = match msg.as_str() { Some(val) => val.to_string(), None => msg.to_string() };
+            return Some(Error::ApiError(format!("{}: {}", kind, msg)))
+        }
+
+        None
+
+    }
+
     // todo: this doesn't work rn
     //       we get a "gateway error" if the request query params are wrong
     fn has_csrf_token_error(value: &JsonValue) -> bool {
@@ -188,6 +848,12 @@ impl Session {
 
 }
 
+/// The user that owns all the "Top <Country>" chart playlists.
+const CHARTS_USER: UserId = UserId(637006841);
+
+/// How many redirects are followed when streaming a podcast episode.
+const MAX_REDIRECTS: usize = 10;
+
 /// Used to decorate a request with the necessery cookies
 struct Middleware {
     user_agent: String,
@@ -270,6 +936,87 @@ impl<'de> Details<'de, UserFamily> for User {
     }
 }
 
+pub struct FavoriteQuery {
+    pub(crate) method: &'static str,
+    pub(crate) body: serde_json::Value,
+}
+
+/// Something that can be added to the users favorites.
+pub trait Favorite {
+    /// The tab of the profile page that lists these favorites.
+    const TAB: &'static str;
+    fn add_query(&self) -> FavoriteQuery;
+    fn remove_query(&self) -> FavoriteQuery;
+}
+
+impl Favorite for Track {
+    const TAB: &'static str = "loved";
+    fn add_query(&self) -> FavoriteQuery {
+        FavoriteQuery { method: "favorite_song.add", body: json!({"SNG_ID": self.id}) }
+    }
+    fn remove_query(&self) -> FavoriteQuery {
+        FavoriteQuery { method: "favorite_song.remove", body: json!({"SNG_ID": self.id}) }
+    }
+}
+
+impl Favorite for Album {
+    const TAB: &'static str = "albums";
+    fn add_query(&self) -> FavoriteQuery {
+        FavoriteQuery { method: "album.addFavorite", body: json!({"ALB_ID": self.id}) }
+    }
+    fn remove_query(&self) -> FavoriteQuery {
+        FavoriteQuery { method: "album.deleteFavorite", body: json!({"ALB_ID": self.id}) }
+    }
+}
+
+impl Favorite for Artist {
+    const TAB: &'static str = "artists";
+    fn add_query(&self) -> FavoriteQuery {
+        FavoriteQuery { method: "artist.addFavorite", body: json!({"ART_ID": self.id}) }
+    }
+    fn remove_query(&self) -> FavoriteQuery {
+        FavoriteQuery { method: "artist.deleteFavorite", body: json!({"ART_ID": self.id}) }
+    }
+}
+
+impl Favorite for Playlist {
+    const TAB: &'static str = "playlists";
+    fn add_query(&self) -> FavoriteQuery {
+        FavoriteQuery { method: "playlist.addFavorite", body: json!({"PARENT_PLAYLIST_ID": self.id}) }
+    }
+    fn remove_query(&self) -> FavoriteQuery {
+        FavoriteQuery { method: "playlist.deleteFavorite", body: json!({"PLAYLIST_ID": self.id}) }
+    }
+}
+
+impl Favorite for Show {
+    const TAB: &'static str = "podcasts";
+    fn add_query(&self) -> FavoriteQuery {
+        FavoriteQuery { method: "show.addFavorite", body: json!({"SHOW_ID": self.id}) }
+    }
+    fn remove_query(&self) -> FavoriteQuery {
+        FavoriteQuery { method: "show.deleteFavorite", body: json!({"SHOW_ID": self.id}) }
+    }
+}
+
+impl<'de> Details<'de, ShowDetails> for Show {
+    fn details_query(&self) -> DetailsQuery {
+        DetailsQuery {
+            api: DetailsApi::GwLightApi("deezer.pageShow"), // todo: make "nb" be changable
+            body: json!({"show_id": self.id.to_string(), "lang": "en", "nb": 1000, "start": 0})
+        }
+    }
+}
+
+impl<'de> Details<'de, UserProfile> for SmallUser {
+    fn details_query(&self) -> DetailsQuery {
+        DetailsQuery {
+            api: DetailsApi::GwLightApi("deezer.pageProfile"),
+            body: json!({"user_id": self.id.to_string(), "tab": "home", "nb": 40})
+        }
+    }
+}
+
 impl<'de> Details<'de, TrackLyrics> for Track {
     fn details_query(&self) -> DetailsQuery {
         DetailsQuery {
@@ -279,29 +1026,82 @@ impl<'de> Details<'de, TrackLyrics> for Track {
                 "operationName": "SynchronizedTrackLyrics",
                 "query": "query SynchronizedTrackLyrics($trackId: String!) {\n  track(trackId: $trackId) {\n    ...SynchronizedTrackLyrics\n    __typename\n  }\n}\n\nfragment SynchronizedTrackLyrics on Track {\n  id\n  lyrics {\n    ...Lyrics\n    __typename\n  }\n  album {\n    cover {\n      small: urls(pictureRequest: {width: 100, height: 100})\n      medium: urls(pictureRequest: {width: 264, height: 264})\n      large: urls(pictureRequest: {width: 800, height: 800})\n      explicitStatus\n      __typename\n    }\n    __typename\n  }\n  __typename\n}\n\nfragment Lyrics on Lyrics {\n  id\n  copyright\n  text\n  writers\n  synchronizedLines {\n    ...LyricsSynchronizedLines\n    __typename\n  }\n  __typename\n}\n\nfragment LyricsSynchronizedLines on LyricsSynchronizedLine {\n  lrcTimestamp\n  line\n  lineTranslated\n  milliseconds\n  duration\n  __typename\n}",
                 "variables": {
-                    "trackId": self.id,
+                    "trackId": self.id.to_string(),
                 }
             })
         }
     }
 }
 
-#[derive(Debug, Clone, Default, Deserialize)]
+/// An item returned by `Session::resolve`.
+/// Serializes as `{"track": {..}}`, `{"album": {..}}` etc.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DeezerItem {
+    Track(Track),
+    Album(Album),
+    Artist(Artist),
+    Playlist(Playlist),
+    User(SmallUser),
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct SmallUser {
-    #[serde(rename = "USER_ID", deserialize_with = "des_parse_str")]
-    pub id: usize,
-    #[serde(rename = "BLOG_NAME")]
+    #[serde(alias = "USER_ID")]
+    pub id: UserId,
+    #[serde(alias = "BLOG_NAME")]
     pub name: String,
 }
 
-#[derive(Debug, Clone, Default)]
+/// The public profile page of a user.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct UserProfile {
+    pub user: SmallUser,
+    pub playlists: Vec<Playlist>,
+    pub artists: Vec<Artist>,
+    pub followers: usize,
+    pub followings: usize,
+    /// If the logged in user follows this user.
+    pub following: bool,
+}
+
+impl<'de> Deserialize<'de> for UserProfile {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let mut value: JsonValue = Deserialize::deserialize(deserializer)?;
+        if value.get("DATA").is_some() {
+            // bring deezers response into the form we serialize to
+            value = json!({
+                "user": take_json(&mut value, "/DATA/USER"),
+                "playlists": take_json(&mut value, "/TAB/home/playlists/data"),
+                "artists": take_json(&mut value, "/TAB/home/artists/data"),
+                "followers": take_json(&mut value, "/DATA/NB_FOLLOWERS"),
+                "followings": take_json(&mut value, "/DATA/NB_FOLLOWINGS"),
+                "following": take_json(&mut value, "/DATA/IS_FOLLOW"),
+            });
+        }
+        let user = Deserialize::deserialize(take_json(&mut value, "/user")).map_err(|_| serde::de::Error::missing_field("user"))?;
+        let playlists = if value["playlists"].is_null() { Vec::new() } else { Deserialize::deserialize(take_json(&mut value, "/playlists")).map_err(serde::de::Error::custom)? };
+        let artists = if value["artists"].is_null() { Vec::new() } else { Deserialize::deserialize(take_json(&mut value, "/artists")).map_err(serde::de::Error::custom)? };
+        let followers = value["followers"].as_u64().unwrap_or_default() as usize;
+        let followings = value["followings"].as_u64().unwrap_or_default() as usize;
+        let following = value["following"].as_bool().unwrap_or_default();
+        Ok(UserProfile { user, playlists, artists, followers, followings, following })
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
 pub struct UserFamily {
     pub users: Vec<SmallUser>,
 }
 
 impl<'de> Deserialize<'de> for UserFamily {
     fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
-        let value: JsonValue = Deserialize::deserialize(deserializer)?;
+        let mut value: JsonValue = Deserialize::deserialize(deserializer)?;
+        if value.is_object() {
+            // serialized by us
+            let users = Deserialize::deserialize(take_json(&mut value, "/users")).map_err(|_| serde::de::Error::missing_field("users"))?;
+            return Ok(UserFamily { users })
+        }
         let mut users = Vec::new();
         let items = match value.as_array() { Some(val) => val, None => return Err(serde::de::Error::custom("UserFamily: Expected array of users")) };
         for item in items {
@@ -311,81 +1111,515 @@ impl<'de> Deserialize<'de> for UserFamily {
     }
 }
 
-#[derive(Debug, Clone, Default)]
+#[derive(Debug, Clone, Default, Serialize)]
 pub struct UserLibrary {
     pub playlists: Vec<Playlist>,
-    pub history: Vec<String>,
+    /// The search history, most recent first.
+    pub history: Vec<SearchHistoryEntry>,
     // todo: add "notifications"
 }
 
 impl<'de> Deserialize<'de> for UserLibrary {
     fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
         let mut value: JsonValue = Deserialize::deserialize(deserializer)?;
-        let playlists = Deserialize::deserialize(value["PLAYLISTS"].take()).map_err(|_| serde::de::Error::missing_field("PLAYLISTS"))?;
-        let history_raw: Vec<JsonValue> = Deserialize::deserialize(value["SEARCH_HISTORY"].take()).map_err(|_| serde::de::Error::missing_field("SEARCH_HISTORY"))?;
+        if value.get("PLAYLISTS").is_none() {
+            // serialized by us
+            let playlists = Deserialize::deserialize(take_json(&mut value, "/playlists")).map_err(|_| serde::de::Error::missing_field("playlists"))?;
+            let history = Deserialize::deserialize(take_json(&mut value, "/history")).map_err(|_| serde::de::Error::missing_field("history"))?;
+            return Ok(UserLibrary { playlists, history })
+        }
+        let playlists = Deserialize::deserialize(take_json(&mut value, "/PLAYLISTS")).map_err(|_| serde::de::Error::missing_field("PLAYLISTS"))?;
+        let history_raw: Vec<JsonValue> = Deserialize::deserialize(take_json(&mut value, "/SEARCH_HISTORY")).map_err(|_| serde::de::Error::missing_field("SEARCH_HISTORY"))?;
         let mut history = Vec::new();
         for item in history_raw {
-            history.push(match item["query"].as_str() { Some(val) => val.to_string(), None => return Err(serde::de::Error::missing_field("query")) })
+            history.push(Deserialize::deserialize(item).map_err(serde::de::Error::custom)?)
         }
         Ok(UserLibrary { playlists, history })
     }
 }
 
-#[derive(Debug, Clone, Default)]
+/// The logged in user.
+/// The session tokens are never serialized, a deserialized `User` can't be used for authentication.
+#[derive(Debug, Clone, Default, Serialize)]
 pub struct User {
+    #[serde(skip)]
     api_token: String,
+    #[serde(skip)]
     license_token: String,
-    pub id: usize,
+    pub id: UserId,
     pub created: String,
     pub name: String,
     pub multiaccount: bool,
+    /// The id of the "Loved Tracks" playlist.
+    pub loved_tracks: PlaylistId,
 
 }
 
 impl<'de> Deserialize<'de> for User {
     fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
         let value: JsonValue = Deserialize::deserialize(deserializer)?;
+        if value.get("USER").is_none() {
+            // serialized by us
+            let id = Deserialize::deserialize(&value["id"]).map_err(|_| serde::de::Error::missing_field("id"))?;
+            let created = match value["created"].as_str() { Some(val) => val.to_string(), None => return Err(serde::de::Error::missing_field("created")) };
+            let name = match value["name"].as_str() { Some(val) => val.to_string(), None => return Err(serde::de::Error::missing_field("name")) };
+            let multiaccount = value["multiaccount"].as_bool().unwrap_or_default();
+            let loved_tracks = Deserialize::deserialize(&value["loved_tracks"]).unwrap_or_default();
+            return Ok(User { api_token: String::new(), license_token: String::new(), id, created, name, multiaccount, loved_tracks })
+        }
         let api_token = match value["checkForm"].as_str() { Some(val) => val.to_string(), None => return Err(serde::de::Error::missing_field("checkForm")) };
         let license_token = match value["USER"]["OPTIONS"]["license_token"].as_str() { Some(val) => val.to_string(), None => return Err(serde::de::Error::missing_field("license_token")) };
-        let id = match value["USER"]["USER_ID"].as_u64() { Some(val) => val as usize, None => return Err(serde::de::Error::missing_field("USER_ID")) };
+        let id = match value["USER"]["USER_ID"].as_u64() { Some(val) => UserId(val), None => return Err(serde::de::Error::missing_field("USER_ID")) };
         let created = match value["USER"]["INSCRIPTION_DATE"].as_str() { Some(val) => val.to_string(), None => return Err(serde::de::Error::missing_field("INSCRIPTION_DATE")) };
         let name = match value["USER"]["BLOG_NAME"].as_str() { Some(val) => val.to_string(), None => return Err(serde::de::Error::missing_field("BLOG_NAME")) };
         let multiaccount = match value["USER"]["MULTI_ACCOUNT"]["enabled"].as_bool() { Some(val) => val, None => false };
-        Ok(User { api_token, license_token, id, created, name, multiaccount })
+        let loved_tracks = Deserialize::deserialize(&value["USER"]["LOVEDTRACKS_ID"]).unwrap_or_default();
+        Ok(User { api_token, license_token, id, created, name, multiaccount, loved_tracks })
+    }
+}
+
+/// One page of a paginated list.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    /// The index of the first item.
+    pub start: usize,
+    /// The total number of items.
+    pub total: usize,
+}
+
+impl<T: DeserializeOwned> Page<T> {
+
+    /// Parse deezers `{"data": [..], "total": ..}` objects.
+    pub(crate) fn from_response(mut value: JsonValue, start: usize) -> Result<Self, Error> {
+        let items: Vec<T> = Deserialize::deserialize(take_json(&mut value, "/data"))?;
+        let total = match value["total"].as_u64() { Some(val) => val as usize, None => start + items.len() };
+        Ok(Page { items, start, total })
     }
+
 }
 
-#[derive(Debug, Clone, Default, Deserialize)]
+impl<T> Page<T> {
+
+    /// The start of the next page, if there is one.
+    pub fn next(&self) -> Option<usize> {
+        let end = self.start + self.items.len();
+        if end < self.total && !self.items.is_empty() { Some(end) } else { None }
+    }
+
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct SearchResult {
-    #[serde(rename = "TOP_RESULT", deserialize_with = "des_array_to_option")]
+    #[serde(alias = "TOP_RESULT", deserialize_with = "des_array_to_option")]
     pub top: Option<Artist>,
-    #[serde(rename = "TRACK", deserialize_with = "des_after_data")]
+    #[serde(alias = "TRACK", deserialize_with = "des_after_data")]
     pub tracks: Vec<Track>,
-    #[serde(rename = "ARTIST", deserialize_with = "des_after_data")]
+    #[serde(alias = "ARTIST", deserialize_with = "des_after_data")]
     pub artists: Vec<Artist>,
-    #[serde(rename = "ALBUM", deserialize_with = "des_after_data")]
+    #[serde(alias = "ALBUM", deserialize_with = "des_after_data")]
     pub albums: Vec<Album>,
-    #[serde(rename = "PLAYLIST", deserialize_with = "des_after_data")]
+    #[serde(alias = "PLAYLIST", deserialize_with = "des_after_data")]
     pub playlists: Vec<Playlist>,
-    #[serde(default, rename = "REVISED_QUERY")]
+    #[serde(default, alias = "SHOW", deserialize_with = "des_after_data")]
+    pub shows: Vec<Show>,
+    #[serde(default, alias = "EPISODE", deserialize_with = "des_after_data")]
+    pub episodes: Vec<Episode>,
+    #[serde(default, alias = "REVISED_QUERY")]
     pub revised_query: Option<String>,
 }
 
-#[derive(Debug, Default, Clone, Deserialize)]
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
 pub struct Track {
-    #[serde(rename = "SNG_ID", deserialize_with = "des_parse_str")]
-    pub id: u64,
-    #[serde(rename = "SNG_TITLE")]
+    #[serde(alias = "SNG_ID")]
+    pub id: TrackId,
+    #[serde(alias = "SNG_TITLE")]
     pub name: String,
-    #[serde(rename = "ARTISTS")]
+    #[serde(alias = "ARTISTS")]
     pub artists: Vec<Artist>,
-    #[serde(rename = "MD5_ORIGIN")]
+    #[serde(alias = "MD5_ORIGIN")]
     md5_origin: String,
-    #[serde(rename = "MEDIA_VERSION", deserialize_with = "des_parse_str")]
+    #[serde(alias = "MEDIA_VERSION", deserialize_with = "des_parse_str")]
     media_version: u64,
+    /// In seconds.
+    #[serde(default, alias = "DURATION", deserialize_with = "des_parse_str")]
+    pub duration: u64,
+    #[serde(default, alias = "FILESIZE_MP3_128", deserialize_with = "des_parse_str")]
+    filesize_mp3_128: u64,
+    #[serde(default, alias = "FILESIZE_MP3_320", deserialize_with = "des_parse_str")]
+    filesize_mp3_320: u64,
+    #[serde(default, alias = "FILESIZE_FLAC", deserialize_with = "des_parse_str")]
+    filesize_flac: u64,
+}
+
+impl Track {
+
+    /// The size of the track in this format, `None` if it isn't available in it.
+    pub fn size(&self, format: Format) -> Option<u64> {
+        let size = match format {
+            Format::Mp3_128 => self.filesize_mp3_128,
+            Format::Mp3_320 => self.filesize_mp3_320,
+            Format::Flac => self.filesize_flac,
+        };
+        if size > 0 { Some(size) } else { None }
+    }
+
+}
+
+/// The audio format of a track.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Format {
+    #[default]
+    #[serde(rename = "MP3_128")]
+    Mp3_128,
+    #[serde(rename = "MP3_320")]
+    Mp3_320,
+    #[serde(rename = "FLAC")]
+    Flac,
+}
+
+impl Format {
+
+    /// The file extension used for this format.
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Self::Mp3_128 | Self::Mp3_320 => "mp3",
+            Self::Flac => "flac",
+        }
+    }
+
+    /// The name deezer uses for this format.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Mp3_128 => "MP3_128",
+            Self::Mp3_320 => "MP3_320",
+            Self::Flac => "FLAC",
+        }
+    }
+
+    pub(crate) fn quality(&self) -> usize {
+        match self {
+            Self::Mp3_128 => 1,
+            Self::Mp3_320 => 3,
+            Self::Flac => 9,
+        }
+    }
+
+    /// Roughly how many bytes make up one second of audio.
+    pub(crate) fn bytes_per_second(&self) -> u64 {
+        match self {
+            Self::Mp3_128 => 128_000 / 8,
+            Self::Mp3_320 => 320_000 / 8,
+            Self::Flac => 1_000_000 / 8,
+        }
+    }
+
+}
+
+/// An entry in the search history, either a search query
+/// or an item the user clicked on in the search results.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum SearchHistoryEntry {
+    Query {
+        query: String,
+    },
+    Track {
+        id: TrackId,
+        name: String,
+    },
+    Artist {
+        id: ArtistId,
+        name: String,
+    },
+    Album {
+        id: AlbumId,
+        name: String,
+    },
+    /// Something we don't know yet, like a playlist, podcast or radio.
+    /// The raw type and id are kept, so it can still be removed.
+    Other {
+        kind: String,
+        id: String,
+        name: String,
+    },
 }
 
-#[derive(Debug, Default, Clone)]
+impl SearchHistoryEntry {
+
+    /// The entry in the shape deezer uses, plain queries don't have a type.
+    pub(crate) fn to_json(&self) -> JsonValue {
+        match self {
+            Self::Query { query } => json!({"query": query}),
+            Self::Track { id, name } => json!({"type": "track", "id": id.to_string(), "title": name}),
+            Self::Artist { id, name } => json!({"type": "artist", "id": id.to_string(), "title": name}),
+            Self::Album { id, name } => json!({"type": "album", "id": id.to_string(), "title": name}),
+            Self::Other { kind, id, name } => json!({"type": kind, "id": id, "title": name}),
+        }
+    }
+
+}
+
+impl<'de> Deserialize<'de> for SearchHistoryEntry {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let mut value: JsonValue = Deserialize::deserialize(deserializer)?;
+        let kind = value.get("type").and_then(JsonValue::as_str).unwrap_or("query").to_string();
+        // deezer calls it title, we call it name
+        let name = match value.get("name").or(value.get("title")) {
+            Some(JsonValue::String(val)) => val.clone(),
+            _ => String::new(),
+        };
+        let id = take_json(&mut value, "/id");
+        let entry = match kind.as_str() {
+            "query" => Self::Query { query: Deserialize::deserialize(take_json(&mut value, "/query")).map_err(serde::de::Error::custom)? },
+            "track" | "song" => Self::Track { id: Deserialize::deserialize(id).map_err(serde::de::Error::custom)?, name },
+            "artist" => Self::Artist { id: Deserialize::deserialize(id).map_err(serde::de::Error::custom)?, name },
+            "album" => Self::Album { id: Deserialize::deserialize(id).map_err(serde::de::Error::custom)?, name },
+            // serialized by us
+            "other" => Self::Other { kind: Deserialize::deserialize(take_json(&mut value, "/kind")).map_err(serde::de::Error::custom)?, id: Deserialize::deserialize(id).map_err(serde::de::Error::custom)?, name },
+            _ => Self::Other {
+                id: match id {
+                    JsonValue::String(val) => val,
+                    JsonValue::Null => String::new(),
+                    other => other.to_string(),
+                },
+                kind,
+                name,
+            },
+        };
+        Ok(entry)
+    }
+}
+
+/// A page of sections, like the home page or a channel.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Channel {
+    #[serde(default)]
+    pub title: String,
+    pub sections: Vec<Section>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Section {
+    #[serde(default)]
+    pub title: String,
+    pub layout: SectionLayout,
+    /// Items of a kind rizzle doesn't know about are left out.
+    #[serde(deserialize_with = "des_known_items")]
+    pub items: Vec<SectionItem>,
+}
+
+/// How a section is displayed.
+/// Serializes as `"carousel"`, `"grid"` or `"other"`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SectionLayout {
+    Carousel,
+    Grid,
+    #[default]
+    Other,
+}
+
+impl<'de> Deserialize<'de> for SectionLayout {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value: String = Deserialize::deserialize(deserializer)?;
+        Ok(match value.as_str() {
+            "carousel" | "horizontal-grid" | "slideshow" => SectionLayout::Carousel,
+            "grid" | "filterable-grid" => SectionLayout::Grid,
+            _ => SectionLayout::Other,
+        })
+    }
+}
+
+/// An item in a `Section`.
+/// Serializes as `{"album": {..}}`, `{"channel": {..}}` etc.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SectionItem {
+    Album(Album),
+    Playlist(Playlist),
+    Artist(Artist),
+    Track(Track),
+    Channel(ChannelLink),
+}
+
+impl<'de> Deserialize<'de> for SectionItem {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let mut value: JsonValue = Deserialize::deserialize(deserializer)?;
+        let (kind, inner) = match value["type"].as_str() {
+            Some("channel") => {
+                let name = value["target"].as_str().unwrap_or_default().trim_start_matches("/channels/").to_string();
+                let title = value["title"].as_str().unwrap_or_default().to_string();
+                return Ok(SectionItem::Channel(ChannelLink { name, title }))
+            },
+            Some(kind) => (kind.to_string(), take_json(&mut value, "/data")),
+            None => match value { // serialized by us
+                JsonValue::Object(map) if map.len() == 1 => map.into_iter().next().unwrap(),
+                _ => return Err(serde::de::Error::missing_field("type")),
+            },
+        };
+        let item = match kind.as_str() {
+            "album" => SectionItem::Album(Deserialize::deserialize(inner).map_err(serde::de::Error::custom)?),
+            "playlist" => SectionItem::Playlist(Deserialize::deserialize(inner).map_err(serde::de::Error::custom)?),
+            "artist" => SectionItem::Artist(Deserialize::deserialize(inner).map_err(serde::de::Error::custom)?),
+            "track" => SectionItem::Track(Deserialize::deserialize(inner).map_err(serde::de::Error::custom)?),
+            "channel" => SectionItem::Channel(Deserialize::deserialize(inner).map_err(serde::de::Error::custom)?),
+            other => return Err(serde::de::Error::unknown_variant(other, &["album", "playlist", "artist", "track", "channel"])),
+        };
+        Ok(item)
+    }
+}
+
+/// A link to another channel, use `Session::channel` to open it.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ChannelLink {
+    pub name: String,
+    pub title: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Chart {
+    pub tracks: Vec<Track>,
+    pub albums: Vec<Album>,
+    pub artists: Vec<Artist>,
+    pub playlists: Vec<Playlist>,
+}
+
+/// The chart playlist of one country, see `Session::country_charts`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CountryChart {
+    /// The name deezer uses in the title, eg. `"France"` or `"USA"`.
+    pub country: String,
+    pub playlist: Playlist,
+}
+
+impl CountryChart {
+
+    /// The charts user also has a few other playlists, only the "Top <Country>" ones are charts.
+    fn from_playlist(playlist: Playlist) -> Option<Self> {
+        let country = playlist.name.strip_prefix("Top ")?.trim().to_string();
+        Some(Self { country, playlist })
+    }
+
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Genre {
+    pub id: GenreId,
+    pub name: String,
+}
+
+/// Where the tracks of a `Session::radio` come from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RadioSource {
+    /// The users Flow, optionally one of the mood or genre variants.
+    Flow(Option<String>),
+    /// Tracks similar to an artist.
+    Artist(ArtistId),
+    /// Tracks similar to a track.
+    Track(TrackId),
+    /// A genre or editorial radio.
+    Radio(RadioId),
+}
+
+impl RadioSource {
+
+    fn query(&self, user: UserId) -> (&'static str, JsonValue) {
+        match self {
+            Self::Flow(None) => ("radio.getUserRadio", json!({"user_id": user})),
+            Self::Flow(Some(config)) => ("radio.getUserRadio", json!({"user_id": user, "config_id": config})),
+            Self::Artist(id) => ("smart.getSmartRadio", json!({"art_id": id})),
+            Self::Track(id) => ("song.getSearchTrackMix", json!({"sng_id": id, "start_with_input_track": false})),
+            Self::Radio(id) => ("radio.getSongs", json!({"radio_id": id, "nb": 25})),
+        }
+    }
+
+}
+
+/// The tracks of a radio that weren't handed out yet.
+#[derive(Default)]
+struct RadioQueue {
+    queue: VecDeque<Track>,
+    /// Every track that was queued, so none of them is played twice.
+    seen: HashSet<TrackId>,
+    /// How many batches in a row had only tracks we already had.
+    stale: usize,
+}
+
+impl RadioQueue {
+
+    /// The radio ends after this many batches without a new track.
+    const MAX_STALE: usize = 3;
+
+    fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+
+    /// Queue the tracks of a batch that weren't seen yet, returns false if the radio is exhausted.
+    fn refill(&mut self, batch: Vec<Track>) -> bool {
+        let before = self.queue.len();
+        for track in batch {
+            if self.seen.insert(track.id) {
+                self.queue.push_back(track);
+            }
+        }
+        self.stale = if self.queue.len() == before { self.stale + 1 } else { 0 };
+        self.stale < Self::MAX_STALE
+    }
+
+    fn pop(&mut self) -> Option<Track> {
+        self.queue.pop_front()
+    }
+
+}
+
+/// A track in the listening history.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    #[serde(flatten)]
+    pub track: Track,
+    /// Unix timestamp of when the track was played.
+    #[serde(alias = "TS", deserialize_with = "des_parse_str")]
+    pub played: u64,
+}
+
+/// An item in the recently played list.
+/// Serializes as `{"album": {..}}`, `{"playlist": {..}}` etc.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RecentItem {
+    Album(Album),
+    Playlist(Playlist),
+    Artist(Artist),
+    Radio(Radio),
+    /// Something we don't know yet, like a show or a livestream.
+    Unknown,
+}
+
+impl<'de> Deserialize<'de> for RecentItem {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value: JsonValue = Deserialize::deserialize(deserializer)?;
+        let (kind, inner) = match value["__TYPE__"].as_str() {
+            Some(kind) => (kind.to_string(), value),
+            None => match value { // serialized by us
+                JsonValue::Object(map) if map.len() == 1 => map.into_iter().next().unwrap(),
+                JsonValue::String(kind) if kind == "unknown" => return Ok(RecentItem::Unknown),
+                _ => return Err(serde::de::Error::missing_field("__TYPE__")),
+            },
+        };
+        let item = match kind.as_str() {
+            "album" => RecentItem::Album(Deserialize::deserialize(inner).map_err(serde::de::Error::custom)?),
+            "playlist" => RecentItem::Playlist(Deserialize::deserialize(inner).map_err(serde::de::Error::custom)?),
+            "artist" => RecentItem::Artist(Deserialize::deserialize(inner).map_err(serde::de::Error::custom)?),
+            "radio" => RecentItem::Radio(Deserialize::deserialize(inner).map_err(serde::de::Error::custom)?),
+            // so a single unknown item doesn't fail the whole page
+            _ => RecentItem::Unknown,
+        };
+        Ok(item)
+    }
+}
+
+#[derive(Debug, Default, Clone, Serialize)]
 pub struct TrackLyrics {
 
 }
@@ -396,62 +1630,144 @@ impl<'de> Deserialize<'de> for TrackLyrics {
     }
 }
 
-#[derive(Debug, Default, Clone, Deserialize)]
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
 pub struct Artist {
-    #[serde(rename = "ART_ID", deserialize_with = "des_parse_str")]
-    pub id: u64,
-    #[serde(rename = "ART_NAME")]
+    #[serde(alias = "ART_ID")]
+    pub id: ArtistId,
+    #[serde(alias = "ART_NAME")]
     pub name: String,
 }
 
-#[derive(Debug, Default, Clone, Deserialize)]
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct Radio {
+    #[serde(alias = "RADIO_ID")]
+    pub id: RadioId,
+    #[serde(alias = "TITLE")]
+    pub name: String,
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
 pub struct ArtistDetails {
-    #[serde(rename = "ALBUMS", deserialize_with = "des_after_data")]
+    #[serde(alias = "ALBUMS", deserialize_with = "des_after_data")]
     pub albums: Vec<Album>,
-    #[serde(rename = "TOP", deserialize_with = "des_after_data")]
+    #[serde(alias = "TOP", deserialize_with = "des_after_data")]
     pub top_tracks: Vec<Track>,
-    // #[serde(rename = "HIGHLIGHT", flatten)]
+    // #[serde(alias = "HIGHLIGHT", flatten)]
     // pub highlight: Highlights,
-    #[serde(rename = "RELATED_ARTISTS", deserialize_with = "des_after_data")]
+    #[serde(alias = "RELATED_ARTISTS", deserialize_with = "des_after_data")]
     pub related: Vec<Artist>,
 }
 
-#[derive(Debug, Default, Clone, Deserialize)]
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
 pub struct Album {
-    #[serde(rename = "ALB_ID", deserialize_with = "des_parse_str")]
-    pub id: u64,
-    #[serde(rename = "ALB_TITLE")]
+    #[serde(alias = "ALB_ID")]
+    pub id: AlbumId,
+    #[serde(alias = "ALB_TITLE")]
     pub name: String,
-    #[serde(rename = "PHYSICAL_RELEASE_DATE")]
+    #[serde(alias = "PHYSICAL_RELEASE_DATE")]
     pub release_date: String,
 }
 
-#[derive(Debug, Default, Clone, Deserialize)]
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
 pub struct AlbumDetails {
-    #[serde(rename = "SONGS", deserialize_with = "des_after_data")]
+    #[serde(alias = "SONGS", deserialize_with = "des_after_data")]
     pub tracks: Vec<Track>,
 }
 
-#[derive(Debug, Default, Clone, Deserialize)]
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
 pub struct Playlist {
-    #[serde(rename = "PLAYLIST_ID", deserialize_with = "des_parse_str")]
-    pub id: u64,
-    #[serde(rename = "TITLE")]
+    #[serde(alias = "PLAYLIST_ID")]
+    pub id: PlaylistId,
+    #[serde(alias = "TITLE")]
     pub name: String,
-    #[serde(rename = "DATE_MOD")]
+    #[serde(alias = "DATE_MOD")]
     pub last_modified: String,
-    #[serde(rename = "NB_SONG")]
+    #[serde(alias = "NB_SONG")]
     pub songs: usize,
+    /// Zero if unknown.
+    #[serde(default, alias = "PARENT_USER_ID")]
+    pub owner: UserId,
+    /// Everyone can add, remove and reorder the tracks of a collaborative playlist.
+    #[serde(default, alias = "STATUS", deserialize_with = "des_collaborative")]
+    pub collaborative: bool,
+}
+
+/// Used to create or update a playlist.
+#[derive(Debug, Clone, Default)]
+pub struct PlaylistOptions {
+    pub title: String,
+    pub description: String,
+    pub public: bool,
+    /// Collaborative playlists are always public.
+    pub collaborative: bool,
+}
+
+impl PlaylistOptions {
+
+    fn status(&self) -> u8 {
+        match (self.public, self.collaborative) {
+            (_, true) => 2,
+            (true, false) => 0,
+            (false, false) => 1,
+        }
+    }
+
+}
+
+/// A podcast.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct Show {
+    #[serde(alias = "SHOW_ID")]
+    pub id: ShowId,
+    #[serde(alias = "SHOW_NAME")]
+    pub name: String,
+    #[serde(default, alias = "SHOW_DESCRIPTION")]
+    pub description: String,
 }
 
-#[derive(Debug, Default, Clone, Deserialize)]
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct ShowDetails {
+    #[serde(alias = "EPISODES", deserialize_with = "des_after_data")]
+    pub episodes: Vec<Episode>,
+}
+
+/// An episode of a podcast.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct Episode {
+    #[serde(alias = "EPISODE_ID")]
+    pub id: EpisodeId,
+    #[serde(alias = "EPISODE_TITLE")]
+    pub name: String,
+    #[serde(default, alias = "EPISODE_DESCRIPTION")]
+    pub description: String,
+    #[serde(alias = "SHOW_ID")]
+    pub show: ShowId,
+    /// In seconds.
+    #[serde(default, alias = "DURATION", deserialize_with = "des_parse_str")]
+    pub duration: u64,
+    /// Where the user stopped listening, in seconds. Zero if the
+    /// episode wasn't started yet.
+    #[serde(default, alias = "OFFSET", deserialize_with = "des_parse_str")]
+    pub position: u64,
+    /// The audio isn't hosted by deezer and not encrypted.
+    #[serde(default, alias = "EPISODE_DIRECT_STREAM_URL")]
+    url: String,
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
 pub struct PlaylistDetails {
-    #[serde(rename = "SONGS", deserialize_with = "des_after_data")]
+    #[serde(alias = "SONGS", deserialize_with = "des_after_data")]
     pub tracks: Vec<Track>,
 }
 
+/// Parses a string, numbers are also accepted since that's how we serialize them.
 fn des_parse_str<'de, D: serde::Deserializer<'de>, T: std::str::FromStr>(deserializer: D) -> Result<T, D::Error> {
-    let string: String = Deserialize::deserialize(deserializer)?;
+    let value: JsonValue = Deserialize::deserialize(deserializer)?;
+    let string = match value {
+        JsonValue::String(val) => val,
+        JsonValue::Number(val) => val.to_string(),
+        other => return Err(serde::de::Error::invalid_type(serde::de::Unexpected::Other(&other.to_string()), &"string or number")),
+    };
     let res = match string.parse() {
         Ok(val) => val,
         Err(..) => return Err(serde::de::Error::invalid_value(serde::de::Unexpected::Other(&string), &&format!("string, parsable as {}", std::any::type_name::<T>())[..]))
@@ -459,15 +1775,83 @@ fn des_parse_str<'de, D: serde::Deserializer<'de>, T: std::str::FromStr>(deseria
     Ok(res)
 }
 
+/// Unwraps deezers `{"data": [..]}` objects, plain arrays (how we serialize them) are passed through.
 fn des_after_data<'de, D: serde::Deserializer<'de>, T: DeserializeOwned>(deserializer: D) -> Result<T, D::Error> {
     let value: serde_json::Value = Deserialize::deserialize(deserializer)?;
-    let data = &value["data"];
-    serde_json::from_value(data.to_owned()).map_err(serde::de::Error::custom)
+    let data = match value {
+        JsonValue::Array(..) => value,
+        JsonValue::Object(mut map) => map.remove("data").unwrap_or_default(),
+        other => return Err(serde::de::Error::custom(format!("expected an array or {{\"data\": [..]}}, got {}", other))),
+    };
+    serde_json::from_value(data).map_err(serde::de::Error::custom)
+}
+
+/// Deezer uses the playlist status for this, 2 means collaborative.
+fn des_collaborative<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<bool, D::Error> {
+    let value: JsonValue = Deserialize::deserialize(deserializer)?;
+    match value {
+        JsonValue::Bool(val) => Ok(val), // serialized by us
+        JsonValue::Null => Ok(false),
+        other => Ok(other.as_u64() == Some(2) || other.as_str() == Some("2")),
+    }
+}
+
+/// Takes a value out of nested objects, `null` if it isn't there.
+/// Unlike indexing, this doesn't panic if the json has an unexpected shape.
+fn take_json(value: &mut JsonValue, pointer: &str) -> JsonValue {
+    value.pointer_mut(pointer).map(JsonValue::take).unwrap_or_default()
+}
+
+/// Collects the ids of a list of items from the public api.
+fn public_ids<T: DeserializeOwned>(items: &JsonValue) -> Vec<T> {
+    let items = match items.as_array() { Some(val) => val, None => return Vec::new() };
+    items.iter().filter_map(|item| Deserialize::deserialize(&item["id"]).ok()).collect()
+}
+
+/// Turns the items of the public api into our models, so they don't have to be fetched
+/// again one by one. Items that can't be parsed are dropped.
+fn public_items<T: DeserializeOwned>(items: &JsonValue, reshape: fn(&JsonValue) -> JsonValue) -> Vec<T> {
+    let items = match items.as_array() { Some(val) => val, None => return Vec::new() };
+    items.iter().filter_map(|item| serde_json::from_value(reshape(item)).ok()).collect()
+}
+
+fn public_album(item: &JsonValue) -> JsonValue {
+    json!({
+        "ALB_ID": item["id"],
+        "ALB_TITLE": item["title"],
+        "PHYSICAL_RELEASE_DATE": item["release_date"].as_str().unwrap_or_default(),
+    })
 }
 
+fn public_artist(item: &JsonValue) -> JsonValue {
+    json!({"ART_ID": item["id"], "ART_NAME": item["name"]})
+}
+
+fn public_playlist(item: &JsonValue) -> JsonValue {
+    let owner = if item["user"].is_object() { &item["user"]["id"] } else { &item["creator"]["id"] };
+    json!({
+        "PLAYLIST_ID": item["id"],
+        "TITLE": item["title"],
+        "DATE_MOD": item["time_mod"].as_u64().map(|val| val.to_string()).unwrap_or_default(),
+        "NB_SONG": item["nb_tracks"].as_u64().unwrap_or_default(),
+        "PARENT_USER_ID": owner.as_u64().unwrap_or_default(),
+    })
+}
+
+/// Drops the section items that can't be parsed, deezer puts a lot of things in there.
+fn des_known_items<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<Vec<SectionItem>, D::Error> {
+    let items: Vec<JsonValue> = Deserialize::deserialize(deserializer)?;
+    Ok(items.into_iter().filter_map(|item| Deserialize::deserialize(item).ok()).collect())
+}
+
+/// Takes the first element of an array, `null` and plain objects (how we serialize them) are also accepted.
 fn des_array_to_option<'de, D: serde::Deserializer<'de>, T: DeserializeOwned>(deserializer: D) -> Result<Option<T>, D::Error> {
-    let mut array: Vec<serde_json::Value> = Deserialize::deserialize(deserializer)?;
-    let elem = array.drain(..).next();
+    let value: serde_json::Value = Deserialize::deserialize(deserializer)?;
+    let elem = match value {
+        serde_json::Value::Array(mut array) => array.drain(..).next(),
+        serde_json::Value::Null => None,
+        other => Some(other),
+    };
     match elem {
         Some(value) => Ok(Some(serde_json::from_value(value).map_err(serde::de::Error::custom)?)),
         None => Ok(None),
@@ -0,0 +1,205 @@
+
+use std::{collections::VecDeque, sync::{Arc, Mutex, MutexGuard, PoisonError}, time::{SystemTime, UNIX_EPOCH}};
+
+use serde_derive::{Serialize, Deserialize};
+use serde_json::{Value as JsonValue, json};
+
+use crate::{Format, TrackId, AlbumId, PlaylistId, ArtistId, RadioId};
+
+/// The app version listens are reported with, copied from what the deezer web player sends.
+/// It encodes the build date (2023-05-25 14:27:40), deezer might reject it once it's too old.
+const APP_VERSION: &str = "10020230525142740";
+
+/// A track the user listened to, see `Session::report_listen`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Listen {
+    pub track: TrackId,
+    pub format: Format,
+    /// How long the track was listened to, in seconds.
+    pub duration: u64,
+    /// Unix timestamp of when the playback started.
+    pub timestamp: u64,
+    pub context: ListenContext,
+}
+
+impl Listen {
+
+    /// A listen that starts now.
+    pub fn new(track: TrackId, format: Format, context: ListenContext) -> Self {
+        Self { track, format, duration: 0, timestamp: unix_now(), context }
+    }
+
+    pub(crate) fn to_json(&self) -> JsonValue {
+        let (kind, id) = self.context.to_json();
+        json!({
+            "params": {
+                "timestamp": self.timestamp + self.duration,
+                "ts_listen": self.timestamp,
+                "type": 1,
+                "stat": {"seek": 0, "pause": 0, "sync": 1},
+                "media": {"id": self.track, "type": "song", "format": self.format.as_str()},
+                "lt": self.duration,
+                "ctxt": {"t": kind, "id": id},
+                "dev": {"v": APP_VERSION, "t": 0},
+                "ls": [],
+                "is_shuffle": false,
+                "stream_id": "",
+            }
+        })
+    }
+
+}
+
+/// Where the user started playing a track from.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ListenContext {
+    Album(AlbumId),
+    Playlist(PlaylistId),
+    Artist(ArtistId),
+    Radio(RadioId),
+    Flow,
+    Search,
+    Unknown,
+}
+
+impl ListenContext {
+
+    fn to_json(&self) -> (&'static str, String) {
+        match self {
+            Self::Album(id) => ("album_page", id.to_string()),
+            Self::Playlist(id) => ("playlist_page", id.to_string()),
+            Self::Artist(id) => ("artist_top", id.to_string()),
+            Self::Radio(id) => ("radio_page", id.to_string()),
+            Self::Flow => ("dynamic_page_user_radio", String::new()),
+            Self::Search => ("search_page", String::new()),
+            Self::Unknown => ("unknown", String::new()),
+        }
+    }
+
+}
+
+/// Collects listens until they are sent with `Session::flush_listens`.
+///
+/// Attach it to a stream with `Mp3Stream::report_to` to queue a listen
+/// automatically once enough of the track was read.
+/// The queue can be saved with `pending` and restored with `from_pending`
+/// to keep listens that couldn't be sent while offline.
+#[derive(Debug, Clone)]
+pub struct ListenReporter {
+    queue: Arc<Mutex<VecDeque<Listen>>>,
+    threshold: u64,
+}
+
+impl Default for ListenReporter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ListenReporter {
+
+    pub fn new() -> Self {
+        Self::from_pending(Vec::new())
+    }
+
+    pub fn from_pending(pending: Vec<Listen>) -> Self {
+        Self { queue: Arc::new(Mutex::new(pending.into())), threshold: 30 }
+    }
+
+    /// Only report tracks that were listened to for at least this many seconds. Defaults to 30.
+    pub fn threshold(mut self, seconds: u64) -> Self {
+        self.threshold = seconds;
+        self
+    }
+
+    pub fn queue(&self, listen: Listen) {
+        self.lock().push_back(listen);
+    }
+
+    /// The listens that were not sent yet.
+    pub fn pending(&self) -> Vec<Listen> {
+        self.lock().iter().cloned().collect()
+    }
+
+    pub(crate) fn pop(&self) -> Option<Listen> {
+        self.lock().pop_front()
+    }
+
+    /// Put back a listen that couldn't be sent.
+    pub(crate) fn unpop(&self, listen: Listen) {
+        self.lock().push_front(listen);
+    }
+
+    /// A listen is just data, so a thread that panicked while holding the lock can't break the queue.
+    fn lock(&self) -> MutexGuard<'_, VecDeque<Listen>> {
+        self.queue.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+}
+
+/// Keeps track of how much of a track was played and queues a listen
+/// when the stream is finished.
+pub(crate) struct AutoReport {
+    reporter: ListenReporter,
+    listen: Listen,
+    /// The length of the track in seconds, zero if unknown.
+    length: u64,
+    /// Bytes that were read for the first time, skipped or re-read parts don't count.
+    bytes: u64,
+    furthest: u64,
+    /// Set by the player, preferred over the estimate from the bytes read.
+    played: Option<u64>,
+    done: bool,
+}
+
+impl AutoReport {
+
+    pub(crate) fn new(reporter: ListenReporter, listen: Listen, length: u64) -> Self {
+        Self { reporter, listen, length, bytes: 0, furthest: 0, played: None, done: false }
+    }
+
+    /// Some bytes were read starting at `position`.
+    pub(crate) fn advance(&mut self, position: u64, bytes: usize) {
+        let end = position + bytes as u64;
+        if end > self.furthest {
+            self.bytes += end - position.max(self.furthest);
+            self.furthest = end;
+        }
+    }
+
+    pub(crate) fn played(&mut self, seconds: u64) {
+        self.played = Some(seconds);
+    }
+
+    /// How long the track was listened to, in seconds.
+    pub(crate) fn duration(&self) -> u64 {
+        let duration = self.played.unwrap_or(self.bytes / self.listen.format.bytes_per_second());
+        if self.length > 0 { duration.min(self.length) } else { duration }
+    }
+
+    /// Queues the listen if it passed the threshold, only does something the first time.
+    pub(crate) fn finish(&mut self) {
+        if self.done {
+            return
+        }
+        self.done = true;
+        let duration = self.duration();
+        if duration >= self.reporter.threshold {
+            let mut listen = self.listen.clone();
+            listen.duration = duration;
+            self.reporter.queue(listen);
+        }
+    }
+
+}
+
+impl Drop for AutoReport {
+    fn drop(&mut self) {
+        self.finish()
+    }
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|it| it.as_secs()).unwrap_or_default()
+}
@@ -0,0 +1,302 @@
+
+use std::{collections::VecDeque, path::{Path, PathBuf}, sync::{Arc, Mutex, MutexGuard, PoisonError, Condvar, mpsc, atomic::{AtomicBool, Ordering}}, thread, time::Instant, panic::{self, AssertUnwindSafe}, any::Any};
+
+use futures_lite::future;
+
+use crate::{Session, Track, TrackId, Album, AlbumDetails, Playlist, PlaylistDetails, Error, DownloadOptions, download};
+
+/// Something that happened to a download, see `DownloadManager::new`.
+#[derive(Debug, Clone)]
+pub enum DownloadEvent {
+    Queued { track: TrackId },
+    Started { track: TrackId, attempt: u32 },
+    Progress {
+        track: TrackId,
+        /// How many bytes of the file were written.
+        bytes: u64,
+        /// The size of the file, if known.
+        total: Option<u64>,
+        /// In bytes per second.
+        rate: u64,
+    },
+    /// The download failed but will be tried again.
+    Retrying { track: TrackId, attempt: u32, error: String },
+    Finished { track: TrackId, path: PathBuf, size: u64 },
+    Failed { track: TrackId, error: String },
+}
+
+/// What happened to all the downloads, see `DownloadManager::finish`.
+#[derive(Debug, Default)]
+pub struct DownloadReport {
+    pub finished: Vec<(TrackId, PathBuf)>,
+    pub failed: Vec<(TrackId, Error)>,
+}
+
+struct Job {
+    track: Track,
+    path: PathBuf,
+    attempt: u32,
+}
+
+struct Shared {
+    queue: Mutex<VecDeque<Job>>,
+    /// Notified whenever the queue or one of the flags changes.
+    changed: Condvar,
+    paused: AtomicBool,
+    cancelled: AtomicBool,
+    /// No more jobs will be queued.
+    closed: AtomicBool,
+    report: Mutex<DownloadReport>,
+    /// The track every worker is downloading right now.
+    running: Mutex<Vec<Option<TrackId>>>,
+    options: DownloadOptions,
+    retries: u32,
+}
+
+/// Downloads many tracks in the background, using a few worker threads.
+///
+/// Every download is resumable (see `Session::download_to`), so a failed
+/// download continues where it stopped when it's retried.
+/// The bandwidth can be limited using `DownloadOptions::rate_limit`.
+pub struct DownloadManager {
+    shared: Arc<Shared>,
+    events: mpsc::Sender<DownloadEvent>,
+    workers: Vec<thread::JoinHandle<()>>,
+}
+
+impl DownloadManager {
+
+    /// Start `workers` threads which download at most that many tracks at once.
+    /// Failed downloads are tried again up to `retries` times.
+    /// Returns the manager together with a channel that receives all the events.
+    pub fn new(workers: usize, retries: u32, options: DownloadOptions) -> (Self, mpsc::Receiver<DownloadEvent>) {
+
+        let shared = Arc::new(Shared {
+            queue: Mutex::new(VecDeque::new()),
+            changed: Condvar::new(),
+            paused: AtomicBool::new(false),
+            cancelled: AtomicBool::new(false),
+            closed: AtomicBool::new(false),
+            report: Mutex::new(DownloadReport::default()),
+            running: Mutex::new(vec![None; workers.max(1)]),
+            options,
+            retries,
+        });
+
+        let (sender, receiver) = mpsc::channel();
+
+        let workers = (0..workers.max(1)).map(|slot| {
+            let shared = Arc::clone(&shared);
+            let events = sender.clone();
+            thread::spawn(move || worker(shared, events, slot))
+        }).collect();
+
+        (Self { shared, events: sender, workers }, receiver)
+
+    }
+
+    /// Download a track to `path`.
+    pub fn queue(&self, track: Track, path: impl AsRef<Path>) {
+        let _ = self.events.send(DownloadEvent::Queued { track: track.id });
+        lock(&self.shared.queue).push_back(Job { track, path: path.as_ref().to_path_buf(), attempt: 0 });
+        self.shared.changed.notify_all();
+    }
+
+    /// Download all tracks of an album into `dir`, the files are named after the track ids.
+    /// Returns how many tracks were queued.
+    pub async fn queue_album(&self, session: &mut Session, album: &Album, dir: impl AsRef<Path>) -> Result<usize, Error> {
+        let details: AlbumDetails = session.details(album).await?;
+        Ok(self.queue_all(details.tracks, dir.as_ref()))
+    }
+
+    /// Download all tracks of a playlist into `dir`, see `queue_album`.
+    pub async fn queue_playlist(&self, session: &mut Session, playlist: &Playlist, dir: impl AsRef<Path>) -> Result<usize, Error> {
+        let details: PlaylistDetails = session.details(playlist).await?;
+        Ok(self.queue_all(details.tracks, dir.as_ref()))
+    }
+
+    fn queue_all(&self, tracks: Vec<Track>, dir: &Path) -> usize {
+        let count = tracks.len();
+        for track in tracks {
+            let path = dir.join(format!("{}.{}", track.id, self.shared.options.format.extension()));
+            self.queue(track, path);
+        }
+        count
+    }
+
+    /// Stop starting new downloads and hold the running ones.
+    pub fn pause(&self) {
+        self.shared.set(&self.shared.paused, true);
+    }
+
+    pub fn resume(&self) {
+        self.shared.set(&self.shared.paused, false);
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.shared.paused.load(Ordering::Acquire)
+    }
+
+    /// Stop all downloads, queued and running ones fail with `Error::Cancelled`.
+    /// Unfinished files are kept, so they can be resumed later.
+    pub fn cancel(&self) {
+        self.shared.set(&self.shared.cancelled, true);
+    }
+
+    /// How many downloads are waiting to be started.
+    pub fn pending(&self) -> usize {
+        lock(&self.shared.queue).len()
+    }
+
+    /// Wait for all queued downloads to finish, a paused manager is resumed.
+    pub fn finish(mut self) -> DownloadReport {
+
+        // nobody could resume it anymore
+        self.resume();
+        self.shared.set(&self.shared.closed, true);
+        for (slot, worker) in self.workers.drain(..).enumerate() {
+            // downloads catch their own panics, this is just in case the worker itself broke
+            if let Err(payload) = worker.join() {
+                if let Some(track) = lock(&self.shared.running)[slot].take() {
+                    let err = Error::Panicked(panic_message(&*payload));
+                    let _ = self.events.send(DownloadEvent::Failed { track, error: err.to_string() });
+                    lock(&self.shared.report).failed.push((track, err));
+                }
+            }
+        }
+
+        // everything that didn't get to start because of a cancel
+        let mut report = std::mem::take(&mut *lock(&self.shared.report));
+        for job in lock(&self.shared.queue).drain(..) {
+            let _ = self.events.send(DownloadEvent::Failed { track: job.track.id, error: Error::Cancelled.to_string() });
+            report.failed.push((job.track.id, Error::Cancelled));
+        }
+
+        report
+
+    }
+
+}
+
+impl Drop for DownloadManager {
+    fn drop(&mut self) {
+        // the workers stop once the queue is empty, nobody could resume them anymore
+        self.shared.set(&self.shared.paused, false);
+        self.shared.set(&self.shared.closed, true);
+    }
+}
+
+impl Shared {
+
+    fn set(&self, flag: &AtomicBool, value: bool) {
+        let _guard = lock(&self.queue); // so no worker misses the notification
+        flag.store(value, Ordering::Release);
+        self.changed.notify_all();
+    }
+
+    /// Wait for the next job, `None` if the worker should stop.
+    fn next_job(&self) -> Option<Job> {
+        let mut queue = lock(&self.queue);
+        loop {
+            if self.cancelled.load(Ordering::Acquire) {
+                return None
+            }
+            if !self.paused.load(Ordering::Acquire) {
+                if let Some(job) = queue.pop_front() {
+                    return Some(job)
+                }
+                if self.closed.load(Ordering::Acquire) {
+                    return None
+                }
+            }
+            queue = self.changed.wait(queue).unwrap_or_else(PoisonError::into_inner);
+        }
+    }
+
+    /// Called while downloading, blocks while paused.
+    fn check(&self) -> Result<(), Error> {
+        let mut queue = lock(&self.queue);
+        while self.paused.load(Ordering::Acquire) && !self.cancelled.load(Ordering::Acquire) {
+            queue = self.changed.wait(queue).unwrap_or_else(PoisonError::into_inner);
+        }
+        match self.cancelled.load(Ordering::Acquire) {
+            true => Err(Error::Cancelled),
+            false => Ok(()),
+        }
+    }
+
+}
+
+fn worker(shared: Arc<Shared>, events: mpsc::Sender<DownloadEvent>, slot: usize) {
+
+    let mut client = None;
+
+    while let Some(mut job) = shared.next_job() {
+
+        let track = job.track.id;
+        let total = job.track.size(shared.options.format);
+        let _ = events.send(DownloadEvent::Started { track, attempt: job.attempt });
+        lock(&shared.running)[slot] = Some(track);
+
+        let start = Instant::now();
+        let mut started_at = None;
+        let mut progress = |bytes: u64| {
+            shared.check()?;
+            // resumed downloads shouldn't count the bytes that were already there
+            let first = *started_at.get_or_insert(bytes);
+            let elapsed = start.elapsed().as_secs_f64().max(0.001);
+            let rate = ((bytes - first) as f64 / elapsed) as u64;
+            let _ = events.send(DownloadEvent::Progress { track, bytes, total, rate });
+            Ok(())
+        };
+
+        // a bug in one download shouldn't take the whole worker down with it
+        let result = panic::catch_unwind(AssertUnwindSafe(|| match &mut client {
+            Some(val) => future::block_on(download::download(val, &job.track, &job.path, &shared.options, &mut progress)),
+            None => match rtv::SimpleClient::new() {
+                Ok(val) => future::block_on(download::download(client.insert(val), &job.track, &job.path, &shared.options, &mut progress)),
+                Err(err) => Err(Error::from(err)),
+            },
+        }));
+
+        let result = result.unwrap_or_else(|payload| {
+            client = None;
+            Err(Error::Panicked(panic_message(&*payload)))
+        });
+
+        match result {
+            Ok(size) => {
+                let _ = events.send(DownloadEvent::Finished { track, path: job.path.clone(), size });
+                lock(&shared.report).finished.push((track, job.path));
+            },
+            Err(err) if !matches!(err, Error::Cancelled | Error::Panicked(..)) && job.attempt < shared.retries => {
+                client = None; // the connection might be broken
+                job.attempt += 1;
+                let _ = events.send(DownloadEvent::Retrying { track, attempt: job.attempt, error: err.to_string() });
+                lock(&shared.queue).push_back(job);
+                shared.changed.notify_all();
+            },
+            Err(err) => {
+                let _ = events.send(DownloadEvent::Failed { track, error: err.to_string() });
+                lock(&shared.report).failed.push((track, err));
+            },
+        }
+
+        lock(&shared.running)[slot] = None;
+
+    }
+
+}
+
+/// The state stays usable even if a thread panicked while holding a lock.
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    match (payload.downcast_ref::<&str>(), payload.downcast_ref::<String>()) {
+        (Some(msg), _) => msg.to_string(),
+        (_, Some(msg)) => msg.clone(),
+        _ => "unknown panic".to_string(),
+    }
+}
@@ -1,5 +1,5 @@
 
-use crate::{UserInfo, Session};
+use crate::{UserInfo, Session, DeezerId, TrackId, AlbumId, ArtistId, PlaylistId, UserId, SearchResult, UserLibrary, HistoryEntry, RecentItem, SearchHistoryEntry, TrackDecryptor, TrackEncryptor, TrackCipher, track_key, Format, Track, DownloadManager, DownloadOptions, DownloadEvent, Error, RateLimiter, Page, UserProfile, SmallUser, Playlist, RadioQueue, Album, Artist, public_items, public_album, public_artist, public_playlist, throttle::{Throttle, Throttled}};
 
 use std::io::Read;
 use futures_lite::future::block_on;
@@ -80,3 +80,759 @@ fn rizzle_test() {
 
 }
 
+
+#[test]
+fn parse_deezer_ids() {
+
+    assert_eq!(DeezerId::parse("https://www.deezer.com/en/album/302127").unwrap(), DeezerId::Album(AlbumId(302127)));
+    assert_eq!(DeezerId::parse("https://www.deezer.com/pt-br/track/3135556?utm_source=deezer").unwrap(), DeezerId::Track(TrackId(3135556)));
+    assert_eq!(DeezerId::parse("deezer://track/3135556").unwrap(), DeezerId::Track(TrackId(3135556)));
+    assert_eq!(DeezerId::parse("deezer://www.deezer.com/playlist/908622995").unwrap(), DeezerId::Playlist(PlaylistId(908622995)));
+    assert_eq!(DeezerId::parse("deezer.com/artist/27#top").unwrap(), DeezerId::Artist(ArtistId(27)));
+    assert_eq!(DeezerId::parse("https://www.deezer.com/de/profile/5").unwrap(), DeezerId::User(UserId(5)));
+
+    assert!(DeezerId::parse("https://www.example.com/track/3135556").is_err());
+    assert!(DeezerId::parse("https://www.deezer.com/en/show/1234").is_err());
+    assert!(DeezerId::parse("https://www.deezer.com/track/abc").is_err());
+
+}
+
+#[test]
+fn parse_ids() {
+
+    let from_str: TrackId = serde_json::from_str("\"3135556\"").unwrap();
+    let from_num: TrackId = serde_json::from_str("3135556").unwrap();
+    assert_eq!(from_str, from_num);
+    assert_eq!(serde_json::to_string(&from_str).unwrap(), "3135556");
+
+    assert_eq!("302127".parse::<AlbumId>().unwrap(), AlbumId(302127));
+    assert_eq!(UserId(u64::MAX).to_string(), u64::MAX.to_string());
+    assert!(serde_json::from_str::<ArtistId>("\"-1\"").is_err());
+
+}
+
+#[test]
+fn serialize_roundtrip() {
+
+    let raw = serde_json::json!({
+        "TOP_RESULT": [{"ART_ID": "27", "ART_NAME": "Daft Punk"}],
+        "TRACK": {"data": [{
+            "SNG_ID": "3135556", "SNG_TITLE": "Harder, Better, Faster, Stronger",
+            "ARTISTS": [{"ART_ID": "27", "ART_NAME": "Daft Punk"}],
+            "MD5_ORIGIN": "51afcde9f56a132096c0496cc95eb24b", "MEDIA_VERSION": "6"
+        }]},
+        "ARTIST": {"data": []},
+        "ALBUM": {"data": [{"ALB_ID": "302127", "ALB_TITLE": "Discovery", "PHYSICAL_RELEASE_DATE": "2001-03-07"}]},
+        "PLAYLIST": {"data": [{"PLAYLIST_ID": "908622995", "TITLE": "Daft Punk", "DATE_MOD": "2023-01-01 00:00:00", "NB_SONG": 42}]},
+    });
+
+    let result: SearchResult = serde_json::from_value(raw).unwrap();
+    let serialized = serde_json::to_value(&result).unwrap();
+    assert_eq!(serialized["tracks"][0]["id"], 3135556);
+    assert_eq!(serialized["top"]["name"], "Daft Punk");
+
+    let again: SearchResult = serde_json::from_value(serialized.clone()).unwrap();
+    assert_eq!(serde_json::to_value(&again).unwrap(), serialized);
+
+    let raw = serde_json::json!({
+        "PLAYLISTS": [{"PLAYLIST_ID": "1", "TITLE": "Mix", "DATE_MOD": "", "NB_SONG": 0}],
+        "SEARCH_HISTORY": [{"query": "daft punk"}, {"type": "track", "id": "3135556", "title": "Harder, Better, Faster, Stronger"}, {"type": "playlist", "id": "908622995"}],
+    });
+
+    let library: UserLibrary = serde_json::from_value(raw).unwrap();
+    assert!(matches!(library.history[1], SearchHistoryEntry::Track { id: TrackId(3135556), .. }));
+    assert_eq!(library.history[2], SearchHistoryEntry::Other { kind: "playlist".into(), id: "908622995".into(), name: String::new() });
+
+    // sent back to deezer in its own shape, so every entry can be removed
+    assert_eq!(library.history[0].to_json(), serde_json::json!({"query": "daft punk"}));
+    assert_eq!(library.history[1].to_json(), serde_json::json!({"type": "track", "id": "3135556", "title": "Harder, Better, Faster, Stronger"}));
+    assert_eq!(library.history[2].to_json(), serde_json::json!({"type": "playlist", "id": "908622995", "title": ""}));
+    let serialized = serde_json::to_value(&library).unwrap();
+    let again: UserLibrary = serde_json::from_value(serialized.clone()).unwrap();
+    assert_eq!(serde_json::to_value(&again).unwrap(), serialized);
+
+    let raw = serde_json::json!({
+        "SNG_ID": "3135556", "SNG_TITLE": "Harder, Better, Faster, Stronger", "ARTISTS": [],
+        "MD5_ORIGIN": "51afcde9f56a132096c0496cc95eb24b", "MEDIA_VERSION": "6", "TS": 1700000000,
+        "FILESIZE_MP3_128": "3385005", "FILESIZE_FLAC": 0
+    });
+
+    let entry: HistoryEntry = serde_json::from_value(raw).unwrap();
+    assert_eq!(entry.track.size(Format::Mp3_128), Some(3385005));
+    assert_eq!(entry.track.size(Format::Flac), None);
+    let serialized = serde_json::to_value(&entry).unwrap();
+    assert_eq!(serialized["played"], 1700000000);
+    let again: HistoryEntry = serde_json::from_value(serialized.clone()).unwrap();
+    assert_eq!(serde_json::to_value(&again).unwrap(), serialized);
+
+    let raw = serde_json::json!([{"__TYPE__": "show", "SHOW_ID": "1"}, {"__TYPE__": "album", "ALB_ID": "302127", "ALB_TITLE": "Discovery", "PHYSICAL_RELEASE_DATE": ""}]);
+    let items: Vec<RecentItem> = serde_json::from_value(raw).unwrap();
+    assert!(matches!(items[0], RecentItem::Unknown));
+    let again: Vec<RecentItem> = serde_json::from_value(serde_json::to_value(&items).unwrap()).unwrap();
+    assert!(matches!(again[..], [RecentItem::Unknown, RecentItem::Album(..)]));
+
+    let raw = serde_json::json!({"__TYPE__": "radio", "RADIO_ID": "30771", "TITLE": "Electro"});
+    let item: RecentItem = serde_json::from_value(raw).unwrap();
+    let serialized = serde_json::to_value(&item).unwrap();
+    let again: RecentItem = serde_json::from_value(serialized.clone()).unwrap();
+    assert_eq!(serde_json::to_value(&again).unwrap(), serialized);
+
+}
+
+#[test]
+fn decrypt_reader() {
+
+    let data: Vec<u8> = (0..2048 * 4 + 100).map(|idx| (idx * 7 % 251) as u8).collect();
+
+    // decrypting chunk by chunk in place
+    let cipher = TrackCipher::for_track(TrackId(3135556));
+    let mut expected = data.clone();
+    for (index, chunk) in expected.chunks_mut(2048).enumerate() {
+        cipher.decrypt_chunk(chunk, index as u64);
+    }
+    assert_ne!(expected[..2048], data[..2048]);
+    assert_eq!(expected[2048..4096], data[2048..4096]);
+
+    // has to be the same, no matter how small the reads are
+    let mut decryptor = TrackDecryptor::for_track(&data[..], TrackId(3135556));
+    let mut result = Vec::new();
+    let mut buff = [0; 7];
+    loop {
+        let len = decryptor.read(&mut buff).unwrap();
+        if len == 0 { break }
+        result.extend_from_slice(&buff[..len]);
+    }
+    assert_eq!(result, expected);
+
+    // starting in the middle of the track
+    let mut decryptor = TrackDecryptor::for_track(&data[2048 * 3..], TrackId(3135556)).starting_at(2048 * 3 + 5);
+    let mut result = Vec::new();
+    decryptor.read_to_end(&mut result).unwrap();
+    assert_eq!(result, expected[2048 * 3 + 5..]);
+    assert_eq!(decryptor.position(), data.len() as u64);
+
+    // seeking inside of the buffered chunk doesn't need the reader
+    let mut decryptor = TrackDecryptor::for_track(&data[..], TrackId(3135556));
+    decryptor.read_exact(&mut buff).unwrap();
+    assert!(decryptor.seek_buffered(2047));
+    assert!(decryptor.seek_buffered(3));
+    assert!(!decryptor.seek_buffered(2048));
+    let mut result = [0; 7];
+    decryptor.read_exact(&mut result).unwrap();
+    assert_eq!(result, expected[3..10]);
+    assert_eq!(decryptor.position(), 10);
+
+}
+
+#[test]
+fn encrypt_roundtrip() {
+
+    // cheap pseudo random data, so the test doesn't need any dependencies
+    let mut state = 0x2545f491u32;
+    let mut random = move || { state ^= state << 13; state ^= state >> 17; state ^= state << 5; state };
+
+    for len in [0, 1, 2047, 2048, 2049, 2048 * 3, 2048 * 7 + 1000] {
+
+        let data: Vec<u8> = (0..len).map(|_| random() as u8).collect();
+
+        let mut encrypted = Vec::new();
+        TrackEncryptor::for_track(&data[..], TrackId(3135556)).read_to_end(&mut encrypted).unwrap();
+        assert_eq!(encrypted.len(), data.len());
+        if len >= 2048 {
+            assert_ne!(encrypted[..2048], data[..2048]);
+        }
+
+        for size in [1, 3, 8, 1000, 2047, 2048, 2049, 5000, 1 << 20] {
+            let mut decryptor = TrackDecryptor::for_track(&encrypted[..], TrackId(3135556));
+            let mut result = Vec::new();
+            let mut buff = vec![0; size];
+            loop {
+                // vary the size of the reads a bit as well
+                let limit = size.min(1 + random() as usize % size);
+                let read = decryptor.read(&mut buff[..limit]).unwrap();
+                if read == 0 { break }
+                result.extend_from_slice(&buff[..read]);
+            }
+            assert_eq!(result, data, "len {} buffer size {}", len, size);
+        }
+
+    }
+
+}
+
+#[test]
+fn cancel_downloads() {
+
+    let (manager, events) = DownloadManager::new(2, 3, DownloadOptions::default());
+    manager.pause();
+
+    for id in 0..5 {
+        let raw = serde_json::json!({"SNG_ID": id, "SNG_TITLE": "", "ARTISTS": [], "MD5_ORIGIN": "", "MEDIA_VERSION": 0});
+        let track: Track = serde_json::from_value(raw).unwrap();
+        manager.queue(track, format!("/nonexistent/{}.mp3", id));
+    }
+    assert_eq!(manager.pending(), 5);
+
+    // nothing is started while paused
+    manager.cancel();
+    let report = manager.finish();
+    assert!(report.finished.is_empty());
+    assert_eq!(report.failed.len(), 5);
+    assert!(report.failed.iter().all(|(_, err)| matches!(err, Error::Cancelled)));
+
+    let failed = events.iter().filter(|event| matches!(event, DownloadEvent::Failed { .. })).count();
+    assert_eq!(failed, 5);
+
+}
+
+#[test]
+fn finish_paused_downloads() {
+
+    // used to wait for a resume that could never happen
+    let (manager, _events) = DownloadManager::new(2, 0, DownloadOptions::default());
+    manager.pause();
+    let report = manager.finish();
+    assert!(report.finished.is_empty() && report.failed.is_empty());
+
+}
+
+#[test]
+fn drop_paused_downloads() {
+
+    // the workers used to wait for a resume forever
+    let (manager, events) = DownloadManager::new(2, 0, DownloadOptions::default());
+    manager.pause();
+    drop(manager);
+    let result = events.recv_timeout(std::time::Duration::from_secs(5));
+    assert!(matches!(result, Err(std::sync::mpsc::RecvTimeoutError::Disconnected)));
+
+}
+
+#[test]
+fn broken_track_fails() {
+
+    // a track without an md5 origin used to panic the worker, the download just vanished
+    let path = std::env::temp_dir().join(format!("rizzle-broken-{}.mp3", std::process::id()));
+    let (manager, events) = DownloadManager::new(1, 2, DownloadOptions::default());
+    manager.queue(Track { id: TrackId(3135556), ..Default::default() }, &path);
+    let report = manager.finish();
+
+    assert!(matches!(report.failed[..], [(TrackId(3135556), Error::NotFound(..))]));
+    assert!(events.try_iter().any(|event| matches!(event, DownloadEvent::Failed { .. })));
+
+    let _ = std::fs::remove_file(crate::download::with_suffix(&path, ".part"));
+    let _ = std::fs::remove_file(crate::download::with_suffix(&path, ".part.json"));
+
+}
+
+#[test]
+fn rate_limit() {
+
+    use std::time::{Duration, Instant};
+    use futures_lite::AsyncReadExt;
+    use crate::throttle::Bucket;
+
+    // the bucket math, using our own clock
+    let start = Instant::now();
+    let at = |millis| start + Duration::from_millis(millis);
+    let mut bucket = Bucket::new(start);
+
+    // empty at first, reads wait for at most one grant
+    assert_eq!(bucket.take(at(0), 8192, 100), Err(Duration::from_secs_f64(100.0 / 8192.0)));
+    assert_eq!(bucket.take(at(0), 8192, 4096), Err(Duration::from_millis(250)));
+
+    // fills up with time, but only up to a quarter second worth
+    assert_eq!(bucket.take(at(250), 8192, 4096), Ok(2048));
+    assert_eq!(bucket.take(at(10_000), 8192, 65536), Ok(2048));
+    assert_eq!(bucket.take(at(10_000), 8192, 10), Err(Duration::from_secs_f64(10.0 / 8192.0)));
+
+    // refunds can be taken again right away
+    bucket.refund(2048);
+    assert_eq!(bucket.take(at(10_000), 8192, 4096), Ok(2048));
+
+    // a clock going backwards doesn't break anything
+    assert!(bucket.take(at(5_000), 8192, 4096).is_err());
+
+    // everything arrives through the throttle, no matter the rate
+    let data = vec![7; 16 * 1024];
+    let limiter = RateLimiter::new(256 * 1024);
+    for rate in [256 * 1024, 0] {
+        limiter.set_rate(rate);
+        let mut throttle = Throttle::default();
+        throttle.add(limiter.clone());
+        let mut result = Vec::new();
+        block_on(Throttled { reader: &data[..], throttle }.read_to_end(&mut result)).unwrap();
+        assert_eq!(result, data);
+    }
+
+}
+
+#[test]
+fn unexpected_shapes() {
+
+    // these used to panic instead of failing
+    let raw = serde_json::json!({"TRACK": false, "ARTIST": [], "ALBUM": [], "PLAYLIST": []});
+    assert!(serde_json::from_value::<SearchResult>(raw).is_err());
+    assert!(Page::<Track>::from_response(serde_json::json!("oops"), 0).is_err());
+    assert!(serde_json::from_value::<UserProfile>(serde_json::json!({"DATA": 1, "TAB": []})).is_err());
+
+}
+
+#[test]
+fn page_next() {
+
+    let raw = serde_json::json!({"data": [{"id": 1, "name": "a"}, {"id": 2, "name": "b"}], "total": 5});
+    let page = Page::<SmallUser>::from_response(raw, 0).unwrap();
+    assert_eq!(page.items.len(), 2);
+    assert_eq!(page.next(), Some(2));
+
+    // the last page
+    let raw = serde_json::json!({"data": [{"id": 5, "name": "e"}], "total": 5});
+    let page = Page::<SmallUser>::from_response(raw, 4).unwrap();
+    assert_eq!(page.next(), None);
+
+    // without a total, only the items that are there are counted
+    let raw = serde_json::json!({"data": [{"id": 1, "name": "a"}]});
+    let page = Page::<SmallUser>::from_response(raw, 10).unwrap();
+    assert_eq!(page.total, 11);
+    assert_eq!(page.next(), None);
+
+    // an empty page never has a next one, even if deezer claims there are more
+    let raw = serde_json::json!({"data": [], "total": 100});
+    let page = Page::<SmallUser>::from_response(raw, 20).unwrap();
+    assert_eq!(page.next(), None);
+
+}
+
+#[test]
+fn gw_light_errors() {
+
+    // on success deezer sends an empty array or object
+    assert!(Session::gw_light_error(&serde_json::json!({"error": [], "results": {}})).is_none());
+    assert!(Session::gw_light_error(&serde_json::json!({"error": {}, "results": {}})).is_none());
+
+    let err = Session::gw_light_error(&serde_json::json!({"error": {"GATEWAY_ERROR": "invalid api token"}}));
+    assert!(matches!(err, Some(Error::InvalidCredentials)));
+    let err = Session::gw_light_error(&serde_json::json!({"error": {"DATA_ERROR": "playlist not found"}}));
+    assert!(matches!(err, Some(Error::NotFound(msg)) if msg == "playlist not found"));
+    let err = Session::gw_light_error(&serde_json::json!({"error": {"ERROR_PLAYLIST_RIGHTS": "not allowed"}}));
+    assert!(matches!(err, Some(Error::ApiError(msg)) if msg == "ERROR_PLAYLIST_RIGHTS: not allowed"));
+
+    let raw = serde_json::json!({"PLAYLIST_ID": "1", "TITLE": "Mix", "DATE_MOD": "", "NB_SONG": 0, "PARENT_USER_ID": "5", "STATUS": 2});
+    let playlist: Playlist = serde_json::from_value(raw).unwrap();
+    assert!(playlist.collaborative);
+    let again: Playlist = serde_json::from_value(serde_json::to_value(&playlist).unwrap()).unwrap();
+    assert!(again.collaborative);
+
+}
+
+#[test]
+fn radio_refill() {
+
+    let batch = |ids: &[u64]| -> Vec<Track> {
+        ids.iter().map(|id| serde_json::from_value(serde_json::json!({"SNG_ID": id, "SNG_TITLE": "", "ARTISTS": [], "MD5_ORIGIN": "", "MEDIA_VERSION": 0})).unwrap()).collect()
+    };
+
+    let mut queue = RadioQueue::default();
+    assert!(queue.refill(batch(&[1, 2, 3])));
+    assert_eq!(queue.pop().unwrap().id, TrackId(1));
+
+    // tracks that were already queued are dropped
+    assert!(queue.refill(batch(&[3, 4, 1])));
+    let ids: Vec<_> = std::iter::from_fn(|| queue.pop()).map(|track| track.id.0).collect();
+    assert_eq!(ids, [2, 3, 4]);
+    assert!(queue.is_empty());
+
+    // deezer keeps sending the same batch, the radio ends eventually
+    assert!(queue.refill(batch(&[1, 2])));
+    assert!(queue.refill(batch(&[1, 2])));
+    assert!(!queue.refill(batch(&[4])));
+
+    // a new track resets that
+    let mut queue = RadioQueue::default();
+    assert!(queue.refill(batch(&[1])));
+    assert!(queue.refill(batch(&[1])));
+    assert!(queue.refill(batch(&[1, 2])));
+    assert!(queue.refill(batch(&[1])));
+    assert!(queue.refill(batch(&[2])));
+    assert!(!queue.refill(batch(&[1, 2])));
+
+}
+
+#[test]
+fn public_models() {
+
+    let raw = serde_json::json!({
+        "albums": {"data": [{"id": 302127, "title": "Discovery", "release_date": "2001-03-07", "type": "album"}]},
+        "artists": {"data": [{"id": 27, "name": "Daft Punk", "type": "artist"}]},
+        "playlists": {"data": [{"id": 1109890291, "title": "Top France", "nb_tracks": 50, "user": {"id": 637006841}}, {"broken": true}]},
+    });
+
+    let albums: Vec<Album> = public_items(&raw["albums"]["data"], public_album);
+    assert_eq!(albums[0].id, AlbumId(302127));
+    assert_eq!(albums[0].release_date, "2001-03-07");
+    let artists: Vec<Artist> = public_items(&raw["artists"]["data"], public_artist);
+    assert_eq!(artists[0].name, "Daft Punk");
+    let playlists: Vec<Playlist> = public_items(&raw["playlists"]["data"], public_playlist);
+    assert_eq!(playlists.len(), 1);
+    assert_eq!((playlists[0].songs, playlists[0].owner), (50, UserId(637006841)));
+
+    // the country comes from the title, other playlists of the charts user are skipped
+    let mut other = playlists[0].clone();
+    other.name = "Best of 2023".into();
+    let charts: Vec<_> = playlists.into_iter().chain([other]).filter_map(crate::CountryChart::from_playlist).collect();
+    assert_eq!(charts.len(), 1);
+    assert_eq!(charts[0].country, "France");
+
+}
+
+#[test]
+fn split_urls() {
+
+    use crate::url::{split_url, resolve_location};
+
+    assert_eq!(split_url("https://example.com/a/b.mp3?x=1").unwrap(), (true, "example.com", "/a/b.mp3?x=1".to_string()));
+    assert_eq!(split_url("http://example.com").unwrap(), (false, "example.com", "/".to_string()));
+    assert_eq!(split_url("https://example.com?x=1").unwrap(), (true, "example.com", "/?x=1".to_string()));
+    assert_eq!(split_url("https://example.com/a#part").unwrap(), (true, "example.com", "/a".to_string()));
+    assert_eq!(split_url("https://example.com:443/a").unwrap(), (true, "example.com", "/a".to_string()));
+    assert!(split_url("https://example.com:8443/a").is_err());
+    assert!(split_url("ftp://example.com/a").is_err());
+    assert!(split_url("https:///a").is_err());
+
+    assert_eq!(resolve_location(true, "a.com", "/b.mp3").unwrap(), "https://a.com/b.mp3");
+    assert_eq!(resolve_location(false, "a.com", "//cdn.b.com/c.mp3").unwrap(), "http://cdn.b.com/c.mp3");
+    assert_eq!(resolve_location(true, "a.com", "http://b.com/c.mp3").unwrap(), "http://b.com/c.mp3");
+    assert!(resolve_location(true, "a.com", "c.mp3").is_err());
+
+    // episodes without a stream url or duration don't break a whole search
+    let raw = serde_json::json!({"EPISODE_ID": "1", "EPISODE_TITLE": "Pilot", "SHOW_ID": "2"});
+    assert!(serde_json::from_value::<crate::Episode>(raw).is_ok());
+
+}
+
+#[test]
+fn user_profile() {
+
+    let raw = serde_json::json!({
+        "DATA": {"USER": {"USER_ID": "5", "BLOG_NAME": "someone"}, "NB_FOLLOWERS": 12, "NB_FOLLOWINGS": 3, "IS_FOLLOW": true},
+        "TAB": {"home": {
+            "playlists": {"data": [{"PLAYLIST_ID": "1", "TITLE": "Mix", "DATE_MOD": "", "NB_SONG": 4}], "total": 1},
+            "artists": {"data": [{"ART_ID": "27", "ART_NAME": "Daft Punk"}], "total": 1},
+        }},
+    });
+
+    let profile: UserProfile = serde_json::from_value(raw).unwrap();
+    assert_eq!(profile.user.id, UserId(5));
+    assert_eq!((profile.playlists.len(), profile.artists.len()), (1, 1));
+    assert_eq!((profile.followers, profile.followings, profile.following), (12, 3, true));
+
+    let serialized = serde_json::to_value(&profile).unwrap();
+    let again: UserProfile = serde_json::from_value(serialized.clone()).unwrap();
+    assert_eq!(serde_json::to_value(&again).unwrap(), serialized);
+
+    // private profiles don't have a home tab
+    let raw = serde_json::json!({"DATA": {"USER": {"USER_ID": "6", "BLOG_NAME": "private"}}, "TAB": {}});
+    let profile: UserProfile = serde_json::from_value(raw).unwrap();
+    assert!(profile.playlists.is_empty() && profile.artists.is_empty());
+    assert_eq!((profile.followers, profile.following), (0, false));
+
+}
+
+#[test]
+fn listen_reports() {
+
+    use crate::{Listen, ListenContext, ListenReporter, AutoReport};
+
+    let track = Track { id: TrackId(3135556), duration: 60, ..Default::default() };
+    let per_second = Format::Mp3_128.bytes_per_second();
+    let listen = Listen::new(track.id, Format::Mp3_128, ListenContext::Album(AlbumId(302127)));
+
+    // too short
+    let reporter = ListenReporter::new().threshold(30);
+    let mut report = AutoReport::new(reporter.clone(), listen.clone(), track.duration);
+    report.advance(0, 10 * per_second as usize);
+    report.finish();
+    assert!(reporter.pending().is_empty());
+
+    // skipping ahead or reading a part again doesn't count, finishing twice only reports once
+    let mut report = AutoReport::new(reporter.clone(), listen.clone(), track.duration);
+    report.advance(0, 20 * per_second as usize);
+    report.advance(10 * per_second, 20 * per_second as usize);
+    report.advance(100 * per_second, 5 * per_second as usize);
+    assert_eq!(report.duration(), 35);
+    report.finish();
+    report.finish();
+    drop(report);
+    assert_eq!(reporter.pending().iter().map(|it| it.duration).collect::<Vec<_>>(), [35]);
+
+    // the player knows better, but it can't be longer than the track
+    let mut report = AutoReport::new(reporter.clone(), listen.clone(), track.duration);
+    report.played(90);
+    assert_eq!(report.duration(), 60);
+    drop(report);
+
+    // a listen that couldn't be sent stays in front
+    let first = reporter.pop().unwrap();
+    assert_eq!(first.duration, 35);
+    reporter.unpop(first);
+    assert_eq!(reporter.pending().iter().map(|it| it.duration).collect::<Vec<_>>(), [35, 60]);
+
+    let json = reporter.pending()[0].to_json();
+    assert_eq!(json["params"]["media"], serde_json::json!({"id": 3135556, "type": "song", "format": "MP3_128"}));
+    assert_eq!(json["params"]["ctxt"], serde_json::json!({"t": "album_page", "id": "302127"}));
+    assert_eq!(json["params"]["lt"], 35);
+    assert_eq!(json["params"]["timestamp"].as_u64().unwrap(), json["params"]["ts_listen"].as_u64().unwrap() + 35);
+
+}
+
+#[test]
+fn streams_are_send() {
+    fn is_send<T: Send>() {}
+    is_send::<crate::AsyncMp3Stream>();
+    is_send::<crate::Mp3Stream>();
+    fn is_send_val<T: Send>(_: T) {}
+    let _ = |client: &mut rtv::SimpleClient, track: &Track, options: &DownloadOptions, progress: &mut (dyn FnMut(u64) -> Result<(), Error> + Send)| {
+        is_send_val(crate::download::download(client, track, std::path::Path::new("a.mp3"), options, progress));
+    };
+}
+
+#[test]
+fn resume_download() {
+
+    use std::{io, pin::Pin, task::{Context, Poll}};
+    use futures_lite::AsyncRead;
+    use crate::download::{PartFile, PartKey};
+
+    /// Hands out `data` but fails after `left` bytes, like a dropped connection.
+    struct Flaky<'a> { data: &'a [u8], left: usize }
+
+    impl AsyncRead for Flaky<'_> {
+        fn poll_read(mut self: Pin<&mut Self>, _cx: &mut Context<'_>, buff: &mut [u8]) -> Poll<io::Result<usize>> {
+            if self.left == 0 {
+                return Poll::Ready(Err(io::Error::new(io::ErrorKind::ConnectionReset, "connection dropped")))
+            }
+            let len = buff.len().min(self.left).min(self.data.len());
+            buff[..len].copy_from_slice(&self.data[..len]);
+            self.data = &self.data[len..];
+            self.left -= len;
+            Poll::Ready(Ok(len))
+        }
+    }
+
+    let data: Vec<u8> = (0..5 * 512 * 1024u32).map(|it| (it * 7 % 251) as u8).collect();
+    let track = Track { id: TrackId(3135556), ..Default::default() };
+    let options = DownloadOptions { md5: Some(format!("{:x}", md5::compute(&data))), ..Default::default() };
+
+    let path = std::env::temp_dir().join(format!("rizzle-resume-{}.mp3", std::process::id()));
+
+    block_on(async {
+
+        let (mut part, offset) = PartFile::open(&path, PartKey::new(&track, options.format)).await.unwrap();
+        assert_eq!(offset, 0);
+        let mut hasher = Some(part.hash_prefix(offset).await.unwrap());
+        let reader = Flaky { data: &data, left: 3 * 512 * 1024 + 100 };
+        assert!(part.write_from(reader, offset, &mut hasher, &mut |_| Ok(())).await.is_err());
+        drop(part);
+
+        // only the part that was saved is trusted
+        let (mut part, offset) = PartFile::open(&path, PartKey::new(&track, options.format)).await.unwrap();
+        assert_eq!(offset, 1024 * 1024);
+        let mut hasher = Some(part.hash_prefix(offset).await.unwrap());
+        let reader = Flaky { data: &data[offset as usize..], left: usize::MAX };
+        let size = part.write_from(reader, offset, &mut hasher, &mut |_| Ok(())).await.unwrap();
+        assert_eq!(part.finish(&track, &options, size, hasher).await.unwrap(), data.len() as u64);
+
+    });
+
+    assert_eq!(std::fs::read(&path).unwrap(), data);
+    let _ = std::fs::remove_file(&path);
+
+    // everything was saved, but the download didn't get to finish
+    let data = &data[..2 * 1024 * 1024];
+    let track = Track { id: TrackId(3135556), filesize_mp3_128: data.len() as u64, ..Default::default() };
+    let options = DownloadOptions { md5: Some(format!("{:x}", md5::compute(data))), verify_size: true, ..Default::default() };
+
+    block_on(async {
+
+        let (mut part, offset) = PartFile::open(&path, PartKey::new(&track, options.format)).await.unwrap();
+        part.write_from(Flaky { data, left: usize::MAX }, offset, &mut None, &mut |_| Ok(())).await.unwrap();
+        drop(part);
+
+        // a re-encoded track starts over
+        let reencoded = Track { media_version: 7, ..track.clone() };
+        let (part, offset) = PartFile::open(&path, PartKey::new(&reencoded, options.format)).await.unwrap();
+        assert_eq!(offset, 0);
+        drop(part);
+
+        let (mut part, offset) = PartFile::open(&path, PartKey::new(&track, options.format)).await.unwrap();
+        part.write_from(Flaky { data, left: usize::MAX }, offset, &mut None, &mut |_| Ok(())).await.unwrap();
+        drop(part);
+
+        // no request for the empty range after the end of the track
+        let mut client = rtv::SimpleClient::new().unwrap();
+        let size = crate::download::download(&mut client, &track, &path, &options, &mut |_| Ok(())).await.unwrap();
+        assert_eq!(size, data.len() as u64);
+
+    });
+
+    assert_eq!(std::fs::read(&path).unwrap(), data);
+    let _ = std::fs::remove_file(&path);
+
+}
+
+#[test]
+fn cipher_known_answer() {
+
+    // computed with `openssl enc -bf-cbc -nopad -iv 0001020304050607`
+    assert_eq!(&track_key(TrackId(3135556)), b"llfk9f,7e%u`<d49");
+
+    let plain: Vec<u8> = (0..2048).map(|idx| (idx * 7 % 251) as u8).collect();
+    let cipher = TrackCipher::for_track(TrackId(3135556));
+
+    let mut chunk = plain.clone();
+    cipher.encrypt_chunk(&mut chunk, 0);
+    assert_eq!(hex::encode(&chunk[..16]), "d21a24d75a2e13b501f81455ff1456d8");
+    assert_eq!(format!("{:x}", md5::compute(&chunk)), "607104322ab6aab943288206cea0e282");
+
+    cipher.decrypt_chunk(&mut chunk, 3);
+    assert_eq!(chunk, plain);
+
+    // only every third chunk is encrypted
+    cipher.encrypt_chunk(&mut chunk, 1);
+    assert_eq!(chunk, plain);
+
+}
+
+#[test]
+fn segment_split() {
+
+    use crate::download::{split_segments, PartFile, PartKey};
+
+    let ranges = |offset, size, connections| split_segments(offset, size, connections).iter().map(|it| (it.start, it.end)).collect::<Vec<_>>();
+
+    // every segment starts on a chunk boundary and together they cover everything
+    assert_eq!(ranges(0, 10000, 3), [(0, 4096), (4096, 8192), (8192, 10000)]);
+    assert_eq!(ranges(4096, 10000, 4), [(4096, 6144), (6144, 8192), (8192, 10000)]);
+    assert_eq!(ranges(0, 100, 8), [(0, 100)]);
+    assert_eq!(ranges(0, 4096, 0), [(0, 4096)]);
+    assert!(ranges(4096, 4096, 2).is_empty());
+
+    let track = Track { id: TrackId(3135556), ..Default::default() };
+    let path = std::env::temp_dir().join(format!("rizzle-segments-{}.mp3", std::process::id()));
+
+    block_on(async {
+
+        // the file gets its full size right away, so every segment can be written at its offset
+        let (part, segments) = PartFile::open_segmented(&path, PartKey::new(&track, Format::Mp3_128), 10000, 3).await.unwrap();
+        assert_eq!(segments, split_segments(0, 10000, 3));
+        drop(part);
+        assert_eq!(std::fs::metadata(crate::download::with_suffix(&path, ".part")).unwrap().len(), 10000);
+
+        // nothing is complete yet, so a normal download starts over
+        let (part, offset) = PartFile::open(&path, PartKey::new(&track, Format::Mp3_128)).await.unwrap();
+        assert_eq!(offset, 0);
+        drop(part);
+
+    });
+
+    let _ = std::fs::remove_file(crate::download::with_suffix(&path, ".part"));
+    let _ = std::fs::remove_file(crate::download::with_suffix(&path, ".part.json"));
+
+}
+
+#[test]
+fn channel_sections() {
+
+    use crate::{Channel, SectionItem, SectionLayout};
+
+    let raw = serde_json::json!({
+        "title": "Explore",
+        "sections": [{
+            "title": "Moods",
+            "layout": "horizontal-grid",
+            "items": [
+                {"type": "channel", "target": "/channels/chill", "title": "Chill"},
+                {"type": "album", "data": {"ALB_ID": "302127", "ALB_TITLE": "Discovery", "PHYSICAL_RELEASE_DATE": "2001-03-07"}},
+                {"type": "livestream", "data": {"LIVESTREAM_ID": "1"}},
+            ],
+        }, {
+            "layout": "something-new",
+            "items": [],
+        }],
+    });
+
+    let channel: Channel = serde_json::from_value(raw).unwrap();
+    assert_eq!(channel.sections[0].layout, SectionLayout::Carousel);
+    assert_eq!(channel.sections[1].layout, SectionLayout::Other);
+
+    // the livestream is dropped
+    let items = &channel.sections[0].items;
+    assert_eq!(items.len(), 2);
+    assert!(matches!(&items[0], SectionItem::Channel(link) if link.name == "chill" && link.title == "Chill"));
+    assert!(matches!(&items[1], SectionItem::Album(album) if album.id == AlbumId(302127)));
+
+    let serialized = serde_json::to_value(&channel).unwrap();
+    assert_eq!(serialized["sections"][0]["layout"], "carousel");
+    let again: Channel = serde_json::from_value(serialized.clone()).unwrap();
+    assert_eq!(serde_json::to_value(&again).unwrap(), serialized);
+
+}
+
+#[test]
+fn check_ranges() {
+
+    use crate::decrypt::check_range_start;
+
+    assert!(check_range_start(206, "Partial Content", Some("bytes 4096-8191/8192"), 4096).is_ok());
+    assert!(check_range_start(206, "Partial Content", Some("bytes 0-8191/8192"), 4096).is_err());
+    assert!(check_range_start(206, "Partial Content", None, 4096).is_err());
+    assert!(check_range_start(206, "Partial Content", Some("garbage"), 4096).is_err());
+
+    // a cdn that ignores the range sends everything
+    assert!(check_range_start(200, "OK", None, 0).is_ok());
+    assert!(check_range_start(200, "OK", None, 4096).is_err());
+    assert!(matches!(check_range_start(416, "Range Not Satisfiable", None, 8192), Err(Error::HttpError(..))));
+
+}
+
+#[test]
+fn broken_downloads() {
+
+    use crate::{decrypt::check_complete, download::{PartFile, PartKey, with_suffix}};
+
+    // a truncated response decrypts fine, it's just too short
+    let data: Vec<u8> = (0..2048 * 3 + 100).map(|idx| (idx * 7 % 251) as u8).collect();
+    let mut encrypted = Vec::new();
+    TrackEncryptor::for_track(&data[..], TrackId(3135556)).read_to_end(&mut encrypted).unwrap();
+    let mut decryptor = TrackDecryptor::for_track(&encrypted[..5000], TrackId(3135556));
+    let mut result = Vec::new();
+    decryptor.read_to_end(&mut result).unwrap();
+    assert_eq!(result, data[..5000]);
+    let err = check_complete(decryptor.position(), data.len() as u64).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::UnexpectedEof);
+    assert!(check_complete(data.len() as u64, data.len() as u64).is_ok());
+
+    // a file that fails the checks is thrown away
+    let track = Track { id: TrackId(3135556), filesize_mp3_128: data.len() as u64 + 1, ..Default::default() };
+    let path = std::env::temp_dir().join(format!("rizzle-broken-download-{}.mp3", std::process::id()));
+    let checks = [
+        DownloadOptions { md5: Some("0".repeat(32)), ..Default::default() },
+        DownloadOptions { verify_size: true, ..Default::default() },
+    ];
+
+    for options in checks {
+        let result = block_on(async {
+            let (mut part, offset) = PartFile::open(&path, PartKey::new(&track, options.format)).await.unwrap();
+            let mut hasher = options.md5.as_ref().map(|_| md5::Context::new());
+            let size = part.write_from(&data[..], offset, &mut hasher, &mut |_| Ok(())).await.unwrap();
+            part.finish(&track, &options, size, hasher).await
+        });
+        match options.verify_size {
+            true => assert!(matches!(result, Err(Error::SizeMismatch(..)))),
+            false => assert!(matches!(result, Err(Error::ChecksumMismatch(..)))),
+        }
+        assert!(!path.exists() && !with_suffix(&path, ".part").exists() && !with_suffix(&path, ".part.json").exists());
+    }
+
+}
@@ -0,0 +1,195 @@
+
+use std::{io, pin::Pin, future::Future, sync::{Arc, Mutex, PoisonError, atomic::{AtomicU64, Ordering}}, task::{Context, Poll}, time::{Duration, Instant}};
+
+use async_io::Timer;
+use futures_lite::{AsyncRead, ready};
+
+/// Reads are only delayed until this many bytes are available, so they don't get tiny.
+const MIN_GRANT: usize = 2048;
+
+/// Limits how fast tracks are read, in bytes per second.
+///
+/// Clones share the same limit, so one limiter can be used for several streams at once
+/// to limit all of them together. Use `independent` to limit every stream on its own instead.
+/// The rate can be changed at any time using `set_rate`, zero means unlimited.
+#[derive(Debug, Clone)]
+pub struct RateLimiter {
+    rate: Arc<AtomicU64>,
+    bucket: Arc<Mutex<Bucket>>,
+}
+
+#[derive(Debug)]
+pub(crate) struct Bucket {
+    /// How many bytes can be read right now.
+    available: f64,
+    last: Instant,
+}
+
+impl Bucket {
+
+    pub(crate) fn new(now: Instant) -> Self {
+        Self { available: 0.0, last: now }
+    }
+
+    /// Take up to `wanted` bytes at `rate`, or find out how long to wait.
+    pub(crate) fn take(&mut self, now: Instant, rate: u64, wanted: usize) -> Result<usize, Duration> {
+
+        // refill, allowing bursts of a quarter second
+        let capacity = (rate as f64 / 4.0).max(MIN_GRANT as f64);
+        let elapsed = now.saturating_duration_since(self.last).as_secs_f64();
+        self.available = (self.available + elapsed * rate as f64).min(capacity);
+        self.last = now;
+
+        let needed = wanted.min(MIN_GRANT) as f64;
+        if self.available >= needed {
+            let grant = (self.available as usize).min(wanted);
+            self.available -= grant as f64;
+            Ok(grant)
+        } else {
+            Err(Duration::from_secs_f64((needed - self.available) / rate as f64))
+        }
+
+    }
+
+    pub(crate) fn refund(&mut self, amount: usize) {
+        self.available += amount as f64;
+    }
+
+}
+
+impl RateLimiter {
+
+    pub fn new(bytes_per_second: u64) -> Self {
+        Self {
+            rate: Arc::new(AtomicU64::new(bytes_per_second)),
+            bucket: Arc::new(Mutex::new(Bucket::new(Instant::now()))),
+        }
+    }
+
+    /// A limiter that has the same rate as this one, which also changes together with it,
+    /// but counts the bytes on its own.
+    pub fn independent(&self) -> Self {
+        Self {
+            rate: Arc::clone(&self.rate),
+            bucket: Arc::new(Mutex::new(Bucket::new(Instant::now()))),
+        }
+    }
+
+    pub fn set_rate(&self, bytes_per_second: u64) {
+        self.rate.store(bytes_per_second, Ordering::Relaxed);
+    }
+
+    pub fn rate(&self) -> u64 {
+        self.rate.load(Ordering::Relaxed)
+    }
+
+    /// Take up to `wanted` bytes, or find out how long to wait.
+    fn take(&self, wanted: usize) -> Result<usize, Duration> {
+        match self.rate() {
+            0 => Ok(wanted),
+            rate => self.bucket.lock().unwrap_or_else(PoisonError::into_inner).take(Instant::now(), rate, wanted),
+        }
+    }
+
+    /// Give back bytes that were taken but not read.
+    fn refund(&self, amount: usize) {
+        if amount > 0 && self.rate() > 0 {
+            self.bucket.lock().unwrap_or_else(PoisonError::into_inner).refund(amount);
+        }
+    }
+
+}
+
+/// All the limiters applied to one stream.
+#[derive(Debug, Default)]
+pub(crate) struct Throttle {
+    limiters: Vec<RateLimiter>,
+    /// Set while waiting for the limiters to fill up again.
+    timer: Option<Timer>,
+}
+
+impl Clone for Throttle {
+    fn clone(&self) -> Self {
+        // a clone doesn't wait for someone else's timer
+        Self { limiters: self.limiters.clone(), timer: None }
+    }
+}
+
+impl Throttle {
+
+    pub(crate) fn add(&mut self, limiter: RateLimiter) {
+        self.limiters.push(limiter);
+    }
+
+    /// Wait until up to `wanted` bytes can be read, returns how many.
+    pub(crate) fn poll_take(&mut self, cx: &mut Context<'_>, wanted: usize) -> Poll<usize> {
+
+        // the timer always keeps the waker of the latest poll
+        if let Some(timer) = &mut self.timer {
+            ready!(Pin::new(timer).poll(cx));
+            self.timer = None;
+        }
+
+        // every limiter already visited holds exactly `grant` bytes
+        let mut grant = wanted;
+        for (idx, limiter) in self.limiters.iter().enumerate() {
+            match limiter.take(grant) {
+                Ok(val) => {
+                    self.limiters[..idx].iter().for_each(|prev| prev.refund(grant - val));
+                    grant = val;
+                },
+                Err(wait) => {
+                    self.limiters[..idx].iter().for_each(|prev| prev.refund(grant));
+                    let mut timer = Timer::after(wait);
+                    if Pin::new(&mut timer).poll(cx).is_ready() {
+                        cx.waker().wake_by_ref();
+                    } else {
+                        self.timer = Some(timer);
+                    }
+                    return Poll::Pending
+                },
+            }
+        }
+
+        Poll::Ready(grant)
+
+    }
+
+    pub(crate) fn refund(&self, amount: usize) {
+        self.limiters.iter().for_each(|limiter| limiter.refund(amount));
+    }
+
+    /// Read into `buff`, but no faster than allowed.
+    pub(crate) fn poll_read<R: AsyncRead + Unpin>(&mut self, cx: &mut Context<'_>, reader: &mut R, buff: &mut [u8]) -> Poll<io::Result<usize>> {
+
+        if buff.is_empty() {
+            return Poll::Ready(Ok(0))
+        }
+
+        let grant = ready!(self.poll_take(cx, buff.len()));
+        let result = Pin::new(reader).poll_read(cx, &mut buff[..grant]);
+        match &result {
+            Poll::Ready(Ok(bytes_read)) => self.refund(grant - bytes_read),
+            _ => self.refund(grant),
+        }
+
+        result
+
+    }
+
+}
+
+/// A reader limited by a `Throttle`.
+pub(crate) struct Throttled<R> {
+    pub(crate) reader: R,
+    pub(crate) throttle: Throttle,
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for Throttled<R> {
+
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buff: &mut [u8]) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        this.throttle.poll_read(cx, &mut this.reader, buff)
+    }
+
+}
@@ -0,0 +1,156 @@
+
+use std::{fmt, str::FromStr};
+
+use crate::{Error, TrackId, AlbumId, ArtistId, PlaylistId, UserId};
+
+/// The id of something on deezer, parsed from a link.
+///
+/// Supported are web urls (`https://www.deezer.com/en/album/302127`),
+/// app uris (`deezer://track/3135556`) and bare paths (`playlist/1234`).
+/// Use `Session::resolve` to get the actual item.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DeezerId {
+    Track(TrackId),
+    Album(AlbumId),
+    Artist(ArtistId),
+    Playlist(PlaylistId),
+    User(UserId),
+}
+
+impl DeezerId {
+
+    pub fn parse(link: &str) -> Result<Self, Error> {
+
+        let invalid = || Error::InvalidUrl(link.to_string());
+
+        let mut rest = link.trim();
+
+        // strip the scheme
+        for scheme in ["https://", "http://", "deezer://"] {
+            if let Some(val) = rest.strip_prefix(scheme) {
+                rest = val;
+                break
+            }
+        }
+
+        // strip the query and fragment
+        if let Some(idx) = rest.find(['?', '#']) {
+            rest = &rest[..idx];
+        }
+
+        let mut segments = rest.split('/').filter(|it| !it.is_empty()).peekable();
+
+        // strip the host, app uris may or may not contain one
+        if let Some(host) = segments.peek() {
+            if host.contains('.') {
+                if !(*host == "deezer.com" || host.ends_with(".deezer.com")) {
+                    return Err(invalid())
+                }
+                segments.next();
+            }
+        }
+
+        let mut segments: Vec<&str> = segments.collect();
+
+        // localized paths look like `/en/album/302127` or `/pt-br/album/302127`
+        if segments.len() == 3 {
+            segments.remove(0);
+        }
+
+        let (kind, id) = match segments[..] {
+            [kind, id] => (kind, id),
+            _ => return Err(invalid()),
+        };
+
+        let id: u64 = id.parse().map_err(|_| invalid())?;
+
+        match kind {
+            "track" => Ok(Self::Track(TrackId(id))),
+            "album" => Ok(Self::Album(AlbumId(id))),
+            "artist" => Ok(Self::Artist(ArtistId(id))),
+            "playlist" => Ok(Self::Playlist(PlaylistId(id))),
+            "profile" | "user" => Ok(Self::User(UserId(id))),
+            _ => Err(invalid()),
+        }
+
+    }
+
+}
+
+impl FromStr for DeezerId {
+    type Err = Error;
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        Self::parse(value)
+    }
+}
+
+impl fmt::Display for DeezerId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Track(id) => write!(f, "https://www.deezer.com/track/{}", id),
+            Self::Album(id) => write!(f, "https://www.deezer.com/album/{}", id),
+            Self::Artist(id) => write!(f, "https://www.deezer.com/artist/{}", id),
+            Self::Playlist(id) => write!(f, "https://www.deezer.com/playlist/{}", id),
+            Self::User(id) => write!(f, "https://www.deezer.com/profile/{}", id),
+        }
+    }
+}
+
+/// Where a redirect points to, `location` can be relative to the url that was requested.
+pub(crate) fn resolve_location(secure: bool, host: &str, location: &str) -> Result<String, Error> {
+    let scheme = if secure { "https" } else { "http" };
+    if location.starts_with("https://") || location.starts_with("http://") {
+        Ok(location.to_string())
+    } else if let Some(rest) = location.strip_prefix("//") {
+        Ok(format!("{}://{}", scheme, rest))
+    } else if location.starts_with('/') {
+        Ok(format!("{}://{}{}", scheme, host, location))
+    } else {
+        Err(Error::InvalidUrl(location.to_string()))
+    }
+}
+
+/// The value of a response header, the name is case insensitive.
+pub(crate) fn header<'a>(head: &'a rtv::ResponseHead, name: &str) -> Option<&'a str> {
+    head.headers.iter()
+        .find(|header| header.name.eq_ignore_ascii_case(name))
+        .map(|header| header.value.trim())
+}
+
+/// Splits a http(s) url into whether it is secure, the host and the rest.
+/// Default ports are dropped, other ports aren't supported.
+pub(crate) fn split_url(url: &str) -> Result<(bool, &str, String), Error> {
+
+    let (secure, rest) = match url.strip_prefix("https://") {
+        Some(val) => (true, val),
+        None => match url.strip_prefix("http://") {
+            Some(val) => (false, val),
+            None => return Err(Error::InvalidUrl(url.to_string())),
+        },
+    };
+
+    // the fragment is never sent
+    let rest = match rest.find('#') { Some(idx) => &rest[..idx], None => rest };
+
+    let (authority, path) = match rest.find(['/', '?']) {
+        Some(idx) => (&rest[..idx], &rest[idx..]),
+        None => (rest, ""),
+    };
+
+    // a query without a path still needs the leading slash
+    let path = if path.starts_with('/') { path.to_string() } else { format!("/{}", path) };
+
+    let host = match authority.rsplit_once(':') {
+        Some((host, "443")) if secure => host,
+        Some((host, "80")) if !secure => host,
+        Some(..) => return Err(Error::InvalidUrl(url.to_string())),
+        None => authority,
+    };
+
+    if host.is_empty() {
+        return Err(Error::InvalidUrl(url.to_string()))
+    }
+
+    Ok((secure, host, path))
+
+}